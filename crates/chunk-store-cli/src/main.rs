@@ -1,8 +1,11 @@
 //! Chunk Store CLI
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use chunk_store::ModArchive;
+use clap::{Parser, Subcommand, ValueEnum};
+use chunk_store::{
+    ArchiveOptions, BackendKind, ChunkConfig, ChunkingStrategy, Codec, ExtractMode, FileHandling, HashAlgorithm, ModArchive, OnExisting,
+    TrainOptions,
+};
 use std::path::PathBuf;
 use std::time::Instant;
 
@@ -14,8 +17,128 @@ struct Cli {
     command: Commands,
 }
 
+/// What to do when the mod ID is already archived
+#[derive(Clone, Copy, ValueEnum)]
+enum OnExists {
+    /// Replace the existing mod and release its chunks
+    Replace,
+    /// Fail without touching the existing mod
+    Reject,
+    /// Keep the existing mod as an older version
+    Version,
+}
+
+impl From<OnExists> for OnExisting {
+    fn from(value: OnExists) -> Self {
+        match value {
+            OnExists::Replace => OnExisting::Replace,
+            OnExists::Reject => OnExisting::Reject,
+            OnExists::Version => OnExisting::Version,
+        }
+    }
+}
+
+/// Where chunk data is stored
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// One SQLite row per chunk
+    Sqlite,
+    /// Append-only pack files indexed by SQLite
+    Pack,
+    /// One file per chunk, sharded by hash prefix
+    Directory,
+}
+
+impl From<Backend> for BackendKind {
+    fn from(value: Backend) -> Self {
+        match value {
+            Backend::Sqlite => BackendKind::Sqlite,
+            Backend::Pack => BackendKind::Pack,
+            Backend::Directory => BackendKind::Directory,
+        }
+    }
+}
+
+/// How new chunks are encoded (incompressible chunks are always stored raw)
+#[derive(Clone, Copy, ValueEnum)]
+enum ChunkCodec {
+    /// zstd at the store's compression level
+    Zstd,
+    /// lz4: larger, but faster to extract
+    Lz4,
+    /// No compression
+    Raw,
+}
+
+impl From<ChunkCodec> for Codec {
+    fn from(value: ChunkCodec) -> Self {
+        match value {
+            ChunkCodec::Zstd => Codec::Zstd,
+            ChunkCodec::Lz4 => Codec::Lz4,
+            ChunkCodec::Raw => Codec::Raw,
+        }
+    }
+}
+
+/// Hash used to address chunks and checksum files
+#[derive(Clone, Copy, ValueEnum)]
+enum Hash {
+    /// xxh3-128: fast, for trusted local mods
+    Xxh3,
+    /// BLAKE3 truncated to 128 bits: resists crafted collisions from untrusted sources
+    Blake3,
+}
+
+impl From<Hash> for HashAlgorithm {
+    fn from(value: Hash) -> Self {
+        match value {
+            Hash::Xxh3 => HashAlgorithm::Xxh3,
+            Hash::Blake3 => HashAlgorithm::Blake3,
+        }
+    }
+}
+
+/// How extracted files share data with the archive's extract cache
+#[derive(Clone, Copy, ValueEnum)]
+enum Link {
+    /// Hardlink to the cache (do not edit the outputs in place)
+    Hardlink,
+    /// Copy-on-write clone of the cache where the filesystem supports it
+    Reflink,
+}
+
+impl From<Link> for ExtractMode {
+    fn from(value: Link) -> Self {
+        match value {
+            Link::Hardlink => ExtractMode::Hardlink,
+            Link::Reflink => ExtractMode::Reflink,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
+    /// Create an empty archive with the given chunk backend
+    Init {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Chunk backend
+        #[arg(long, value_enum, default_value = "sqlite")]
+        backend: Backend,
+        /// Chunk hash algorithm
+        #[arg(long, value_enum, default_value = "xxh3")]
+        hash: Hash,
+    },
+    /// Move all chunks between the sqlite and pack layouts (can be resumed if interrupted)
+    MigrateBackend {
+        /// Target backend
+        #[arg(value_enum)]
+        to: Backend,
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+    },
     /// Add a mod to the archive
     Add {
         /// Path to the mod directory
@@ -29,6 +152,18 @@ enum Commands {
         /// Mod name
         #[arg(short, long)]
         name: Option<String>,
+        /// What to do if the mod ID already exists
+        #[arg(long, value_enum, default_value = "replace")]
+        on_exists: OnExists,
+        /// Use content-defined chunking with this average chunk size (bytes)
+        #[arg(long, value_name = "AVG_SIZE")]
+        cdc: Option<usize>,
+        /// Memory ceiling for file data in flight while archiving (MB)
+        #[arg(long, default_value = "256")]
+        memory_mb: usize,
+        /// Codec for new chunks (defaults to the archive's codec)
+        #[arg(long, value_enum)]
+        codec: Option<ChunkCodec>,
     },
     /// Extract a mod from the archive
     Extract {
@@ -36,9 +171,15 @@ enum Commands {
         mod_id: String,
         /// Output directory
         output: PathBuf,
+        /// Extract an older version instead of the current one
+        #[arg(long)]
+        version: Option<i64>,
         /// Archive directory
         #[arg(short, long, default_value = "./mod-archive")]
         archive: PathBuf,
+        /// Link files from a shared extract cache instead of writing full copies
+        #[arg(long, value_enum)]
+        link: Option<Link>,
     },
     /// List all mods in the archive
     List {
@@ -54,6 +195,12 @@ enum Commands {
         #[arg(short, long, default_value = "./mod-archive")]
         archive: PathBuf,
     },
+    /// Delete the extract cache used by `extract --link`
+    ClearExtractCache {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+    },
     /// Run garbage collection
     Gc {
         /// Archive directory
@@ -65,6 +212,109 @@ enum Commands {
         /// Archive directory
         #[arg(short, long, default_value = "./mod-archive")]
         archive: PathBuf,
+        /// Show per-mod exclusive and shared usage
+        #[arg(long)]
+        mods: bool,
+    },
+    /// Estimate how much of a mod or folder is already stored, without importing it
+    Analyze {
+        /// Mod directory or single file
+        path: PathBuf,
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Number of overlapping mods to list
+        #[arg(long, default_value = "5")]
+        top: usize,
+        /// Memory ceiling for file data in flight while chunking (MB)
+        #[arg(long, default_value = "256")]
+        memory_mb: usize,
+    },
+    /// Replay the archive under alternative chunking and compression settings
+    Simulate {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Fixed chunk sizes to try (bytes)
+        #[arg(long, value_delimiter = ',', default_value = "4096,16384,65536")]
+        sizes: Vec<usize>,
+        /// Content-defined average chunk sizes to try (bytes)
+        #[arg(long, value_delimiter = ',')]
+        cdc: Vec<usize>,
+        /// zstd levels to try (defaults to the archive's level)
+        #[arg(long, value_delimiter = ',')]
+        levels: Vec<i32>,
+        /// Stop adding mods once this much data has been replayed (MB)
+        #[arg(long)]
+        max_mb: Option<u64>,
+        /// Memory ceiling for file data in flight while replaying (MB)
+        #[arg(long, default_value = "256")]
+        memory_mb: usize,
+    },
+    /// Verify chunk and file integrity
+    Verify {
+        /// Mod ID (verifies all mods and their versions if omitted)
+        mod_id: Option<String>,
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+    },
+    /// Check store consistency (reference counts and orphaned data)
+    Fsck {
+        /// Fix reference counts and quarantine orphaned data
+        #[arg(long)]
+        repair: bool,
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+    },
+    /// Train zstd dictionaries per content class and re-encode existing chunks
+    TrainDict {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Maximum dictionary size in KB
+        #[arg(long, default_value = "110")]
+        max_size: usize,
+        /// Maximum number of sample chunks per class
+        #[arg(long, default_value = "4096")]
+        samples: usize,
+    },
+    /// Re-encode all chunks at a new zstd level (resumes if interrupted)
+    Recompress {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// zstd compression level (1-22)
+        #[arg(long)]
+        level: i32,
+        /// Codec for re-encoded chunks (defaults to the archive's codec)
+        #[arg(long, value_enum)]
+        codec: Option<ChunkCodec>,
+    },
+    /// Re-chunk every mod with a new chunking strategy and reclaim the old chunks
+    Rechunk {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Fixed chunk size (bytes)
+        #[arg(long, required_unless_present = "cdc", conflicts_with = "cdc")]
+        size: Option<usize>,
+        /// Use content-defined chunking with this average chunk size (bytes)
+        #[arg(long, value_name = "AVG_SIZE")]
+        cdc: Option<usize>,
+        /// Memory ceiling for file data in flight while re-chunking (MB)
+        #[arg(long, default_value = "256")]
+        memory_mb: usize,
+    },
+    /// Verify every chunk and switch the archive to another hash algorithm
+    Rehash {
+        /// Archive directory
+        #[arg(short, long, default_value = "./mod-archive")]
+        archive: PathBuf,
+        /// Target hash algorithm
+        #[arg(long, value_enum)]
+        to: Hash,
     },
     /// Batch archive all mods in a directory
     Batch {
@@ -73,6 +323,21 @@ enum Commands {
         /// Archive directory
         #[arg(short, long, default_value = "./mod-archive")]
         archive: PathBuf,
+        /// What to do if a mod ID already exists
+        #[arg(long, value_enum, default_value = "replace")]
+        on_exists: OnExists,
+        /// Use content-defined chunking with this average chunk size (bytes)
+        #[arg(long, value_name = "AVG_SIZE")]
+        cdc: Option<usize>,
+        /// Memory ceiling for file data in flight while archiving (MB)
+        #[arg(long, default_value = "256")]
+        memory_mb: usize,
+        /// Codec for new chunks (defaults to the archive's codec)
+        #[arg(long, value_enum)]
+        codec: Option<ChunkCodec>,
+        /// Number of worker threads chunking and compressing (0 = all cores)
+        #[arg(short, long, default_value = "0")]
+        jobs: usize,
     },
 }
 
@@ -80,16 +345,24 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Add { mod_path, archive, id, name } => {
+        Commands::Add { mod_path, archive, id, name, on_exists, cdc, memory_mb, codec } => {
             let start = Instant::now();
             let mut arch = ModArchive::open(&archive)?;
+            if let Some(avg_size) = cdc {
+                arch.set_chunking(ChunkingStrategy::content_defined(avg_size))?;
+            }
+            arch.set_memory_limit(memory_mb * 1024 * 1024);
+            if let Some(codec) = codec {
+                arch.set_codec(codec.into())?;
+            }
             
             println!("Archiving: {}", mod_path.display());
-            let manifest = arch.archive_mod(
-                &mod_path,
-                id.as_deref(),
-                name.as_deref(),
-            )?;
+            let report = arch.archive_mod_with(&mod_path, &ArchiveOptions {
+                id,
+                name,
+                on_existing: on_exists.into(),
+            })?;
+            let manifest = &report.manifest;
             
             println!("\n✅ Archive complete");
             println!("   ID: {}", manifest.id);
@@ -97,20 +370,46 @@ fn main() -> Result<()> {
             println!("   Original size: {:.2} MB", manifest.original_size as f64 / 1024.0 / 1024.0);
             println!("   Files: {} resource files, {} preserved files", 
                 manifest.files.len(), manifest.preserved_files.len());
+            println!("   Handling: {} dds, {} buffers, {} generic, {} preserved, {} skipped",
+                report.count(FileHandling::Dds),
+                report.count(FileHandling::Buffer),
+                report.count(FileHandling::Generic),
+                report.count(FileHandling::Preserved),
+                report.count(FileHandling::Skipped));
+            if let Some(version) = report.version {
+                println!("   Previous version kept as: {}", version);
+            }
+            let changes = &report.changes;
+            println!("   Changes: {} added, {} modified, {} removed, {} unchanged ({} reused without reading)",
+                changes.added.len(), changes.modified.len(), changes.removed.len(),
+                changes.unchanged, changes.reused);
+            for path in &changes.modified {
+                println!("   ~ {}", path);
+            }
             println!("   Time: {:.2}s", start.elapsed().as_secs_f64());
             
+            for file in report.warnings() {
+                println!("   ⚠️ {}: {}", file.path, file.note.as_deref().unwrap_or_default());
+            }
+            
             let stats = arch.get_stats()?;
             println!("\n📊 Archive stats:");
             println!("   Total mods: {}", stats.mod_count);
             println!("   Dedup ratio: {:.1}%", stats.deduplication_ratio() * 100.0);
         }
         
-        Commands::Extract { mod_id, output, archive } => {
+        Commands::Extract { mod_id, output, version, archive, link } => {
             let start = Instant::now();
-            let arch = ModArchive::open(&archive)?;
+            let mut arch = ModArchive::open(&archive)?;
+            if let Some(link) = link {
+                arch.set_extract_mode(link.into());
+            }
             
             println!("Extracting: {} -> {}", mod_id, output.display());
-            arch.extract_mod(&mod_id, &output)?;
+            match version {
+                Some(version) => arch.extract_version(&mod_id, version, &output)?,
+                None => arch.extract_mod(&mod_id, &output)?,
+            }
             
             println!("✅ Extract complete ({:.2}s)", start.elapsed().as_secs_f64());
         }
@@ -126,6 +425,11 @@ fn main() -> Result<()> {
                 for (id, name, _created_at) in mods {
                     println!("  {}", id);
                     println!("    Name: {}", name);
+                    let versions = arch.list_versions(&id)?;
+                    if !versions.is_empty() {
+                        let list: Vec<String> = versions.iter().map(|(v, _)| v.to_string()).collect();
+                        println!("    Versions: {}", list.join(", "));
+                    }
                     println!();
                 }
             }
@@ -141,6 +445,26 @@ fn main() -> Result<()> {
             }
         }
         
+        Commands::Init { archive, backend, hash } => {
+            let mut arch = ModArchive::create(&archive, backend.into())?;
+            arch.rehash(hash.into())?;
+            println!("✅ Archive ready: {} ({} backend, {})",
+                archive.display(), arch.backend_name(), arch.config().hash_algorithm.name());
+        }
+        
+        Commands::MigrateBackend { to, archive } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            let target: BackendKind = to.into();
+            
+            println!("Migrating chunks: {} → {}...", arch.backend_name(), target.as_str());
+            let moved = arch.migrate_backend(target)?;
+            
+            println!("✅ Complete");
+            println!("   Moved chunks: {}", moved);
+            println!("   Time: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
         Commands::Gc { archive } => {
             let mut arch = ModArchive::open(&archive)?;
             
@@ -152,23 +476,312 @@ fn main() -> Result<()> {
             println!("   Freed space: {:.2} MB", freed as f64 / 1024.0 / 1024.0);
         }
         
-        Commands::Stats { archive } => {
+        Commands::ClearExtractCache { archive } => {
+            let arch = ModArchive::open(&archive)?;
+            let (files, size) = arch.clear_extract_cache()?;
+            
+            println!("✅ Extract cache cleared");
+            println!("   Removed files: {}", files);
+            println!("   Freed space: {:.2} MB", mb(size));
+        }
+        
+        Commands::Stats { archive, mods } => {
             let arch = ModArchive::open(&archive)?;
             let stats = arch.get_stats()?;
             
             println!("📊 Archive statistics:\n");
+            println!("  Backend: {}", arch.backend_name());
             println!("  Mod count: {}", stats.mod_count);
+            println!("  Versions: {}", stats.version_count);
+            if stats.corrupt_manifests > 0 {
+                println!("  Corrupt manifests: {} (not counted, run `verify` for details)", stats.corrupt_manifests);
+            }
             println!("  Unique chunks: {}", stats.unique_chunks);
-            println!("  Stored size: {:.2} MB", stats.total_stored_size as f64 / 1024.0 / 1024.0);
-            println!("  Original size: {:.2} MB", stats.total_original_size as f64 / 1024.0 / 1024.0);
+            println!("  Stored size: {:.2} MB", mb(stats.total_stored_size));
+            println!("  Original size: {:.2} MB", mb(stats.total_original_size));
             println!("  Dedup ratio: {:.1}%", stats.deduplication_ratio() * 100.0);
-            println!("  Space saved: {:.2} MB", 
-                (stats.total_original_size - stats.total_stored_size) as f64 / 1024.0 / 1024.0);
+            println!("  Space saved: {:.2} MB",
+                mb(stats.total_original_size.saturating_sub(stats.total_stored_size)));
+            println!("  Reclaimable: {:.2} MB", mb(stats.reclaimable_size));
+            
+            println!("\n  By class (logical → stored):");
+            for (label, class) in [
+                ("Chunks", &stats.chunks),
+                ("Legacy zst", &stats.compressed),
+                ("Preserved", &stats.preserved),
+                ("Metadata", &stats.metadata),
+            ] {
+                println!("    {:<11} {:>8} items  {:>10.2} MB → {:>10.2} MB",
+                    label, class.items, mb(class.logical_size), mb(class.stored_size));
+            }
+            
+            println!("\n  By codec (original → stored):");
+            for (codec, usage) in &stats.codecs {
+                println!("    {:<11} {:>8} chunks {:>10.2} MB → {:>10.2} MB",
+                    codec, usage.chunks, mb(usage.original_size), mb(usage.stored_size));
+            }
+            
+            if mods {
+                println!("\n  By mod (total / exclusive / shared):");
+                for usage in &stats.mods {
+                    println!("    {}: {:.2} MB / {:.2} MB / {:.2} MB",
+                        usage.id, mb(usage.total_size), mb(usage.exclusive_size), mb(usage.shared_size));
+                }
+            }
+        }
+        
+        Commands::Analyze { path, archive, top, memory_mb } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            arch.set_memory_limit(memory_mb * 1024 * 1024);
+            
+            println!("Analyzing: {}...", path.display());
+            let report = arch.analyze(&path)?;
+            
+            println!("\n  Input: {} chunked files, {} preserved, {:.2} MB",
+                report.files, report.preserved_files, mb(report.original_size));
+            println!("  Already stored: {:.2} MB / {:.2} MB ({:.1}%)",
+                mb(report.existing_size), mb(report.chunked_size), report.existing_ratio() * 100.0);
+            println!("  Projected stored size: {:.2} MB", mb(report.projected_size));
+            if report.overlaps.is_empty() {
+                println!("  No overlap with archived mods");
+            } else {
+                println!("\n  Most overlapping mods:");
+                for overlap in report.overlaps.iter().take(top) {
+                    println!("    {} ({}): {:.2} MB ({:.1}%)",
+                        overlap.id, overlap.name, mb(overlap.shared_size), overlap.ratio * 100.0);
+                }
+            }
+            println!("\n  Time: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
+        Commands::Simulate { archive, sizes, cdc, levels, max_mb, memory_mb } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            arch.set_memory_limit(memory_mb * 1024 * 1024);
+            let levels = if levels.is_empty() { vec![arch.config().compression_level] } else { levels };
+            let strategies = sizes.iter().map(|&size| ChunkingStrategy::Fixed { size })
+                .chain(cdc.iter().map(|&avg_size| ChunkingStrategy::content_defined(avg_size)));
+            let configs: Vec<ChunkConfig> = strategies
+                .flat_map(|strategy| levels.iter().map(move |&level| (strategy, level)))
+                .map(|(strategy, compression_level)| ChunkConfig {
+                    strategy,
+                    compression_level,
+                    ..arch.config().clone()
+                })
+                .collect();
+            
+            println!("Simulating {} configurations...", configs.len());
+            let results = arch.simulate(&configs, max_mb.map(|mb| mb * 1024 * 1024))?;
+            
+            if let Some(first) = results.first() {
+                println!("\n  Replayed: {:.2} MB\n", mb(first.logical_size));
+            }
+            println!("  {:<12} {:>5} {:>10} {:>12} {:>7} {:>10} {:>12}",
+                "Chunking", "Level", "Chunks", "Stored MB", "Dedup", "Index MB", "Decode MB/s");
+            for result in &results {
+                let chunking = match result.config.strategy {
+                    ChunkingStrategy::ContentDefined { avg_size, .. } => format!("cdc {}", avg_size),
+                    strategy => format!("fixed {}", strategy.chunk_size()),
+                };
+                println!("  {:<12} {:>5} {:>10} {:>12.2} {:>6.1}% {:>10.2} {:>12.0}",
+                    chunking, result.config.compression_level, result.unique_chunks, mb(result.stored_size),
+                    result.deduplication_ratio() * 100.0, mb(result.index_size),
+                    result.decode_throughput / 1024.0 / 1024.0);
+            }
+            println!("\n  Time: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
+        Commands::Verify { mod_id, archive } => {
+            let start = Instant::now();
+            let arch = ModArchive::open(&archive)?;
+            
+            let reports = match mod_id {
+                Some(id) => vec![arch.verify_mod(&id)?],
+                None => arch.verify_all()?,
+            };
+            
+            let mut broken = 0;
+            for report in &reports {
+                let label = match report.version {
+                    Some(version) => format!("{}@{}", report.mod_id, version),
+                    None => report.mod_id.clone(),
+                };
+                if report.is_ok() {
+                    println!("✅ {} ({} chunks)", label, report.chunks_checked);
+                    continue;
+                }
+                
+                broken += 1;
+                println!("❌ {}", label);
+                if let Some(error) = &report.manifest_error {
+                    println!("   Error: {}", error);
+                }
+                for hash in &report.corrupted_chunks {
+                    println!("   Corrupted chunk: {}", hash);
+                }
+                for hash in &report.missing_chunks {
+                    println!("   Missing chunk: {}", hash);
+                }
+                for file_id in &report.missing_compressed {
+                    println!("   Missing compressed file: compressed/{}.zst", file_id);
+                }
+                for path in &report.missing_preserved {
+                    println!("   Missing preserved file: {}", path);
+                }
+                for path in &report.checksum_mismatches {
+                    println!("   Checksum mismatch: {}", path);
+                }
+            }
+            
+            println!("\nVerified {} manifests, {} damaged ({:.2}s)",
+                reports.len(), broken, start.elapsed().as_secs_f64());
+            
+            if broken > 0 {
+                anyhow::bail!("{} manifests failed verification", broken);
+            }
+        }
+        
+        Commands::Fsck { repair, archive } => {
+            let mut arch = ModArchive::open(&archive)?;
+            let report = arch.fsck(repair)?;
+            
+            println!("Checked {} mods, {} chunks\n", report.mods_checked, report.chunks_checked);
+            
+            for id in &report.corrupt_manifests {
+                println!("  Corrupt manifest: {}", id);
+            }
+            for (id, hash) in &report.missing_chunks {
+                println!("  Missing chunk: {} (mod {})", hash, id);
+            }
+            for mismatch in &report.ref_mismatches {
+                println!("  Ref count mismatch: {} (expected {}, found {})",
+                    mismatch.hash, mismatch.expected, mismatch.actual);
+            }
+            for hash in &report.orphaned_chunks {
+                println!("  Orphaned chunk: {}", hash);
+            }
+            for name in &report.orphaned_compressed {
+                println!("  Orphaned compressed file: compressed/{}", name);
+            }
+            for name in &report.orphaned_preserved {
+                println!("  Orphaned preserved directory: mods/{}", name);
+            }
+            for name in &report.orphaned_versions {
+                println!("  Orphaned version directory: versions/{}", name);
+            }
+            
+            if report.is_clean() {
+                println!("✅ Store is consistent");
+            } else if report.repaired {
+                println!("\n✅ Repaired {} ref counts, quarantined {} chunks, {} files, {} directories",
+                    report.ref_mismatches.len(),
+                    report.orphaned_chunks.len(),
+                    report.orphaned_compressed.len(),
+                    report.orphaned_preserved.len() + report.orphaned_versions.len());
+                if let Some(path) = &report.quarantine_path {
+                    println!("   Quarantine: {}", path.display());
+                }
+                if !report.missing_chunks.is_empty() {
+                    println!("   ⚠️ Missing chunks cannot be repaired");
+                }
+            } else if repair {
+                anyhow::bail!("repair skipped: {} corrupt manifests", report.corrupt_manifests.len());
+            } else {
+                anyhow::bail!("store is inconsistent, run with --repair to fix");
+            }
+        }
+        
+        Commands::TrainDict { archive, max_size, samples } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            
+            let reports = arch.train_dictionaries(&TrainOptions {
+                max_dict_size: max_size * 1024,
+                max_samples: samples,
+            })?;
+            
+            for report in &reports {
+                match (&report.note, report.dict_id) {
+                    (None, Some(dict_id)) => println!(
+                        "✅ {}: dictionary #{} ({:.1} KB, {} samples), {} chunks re-encoded, {:.2} MB → {:.2} MB",
+                        report.class, dict_id, report.dict_size as f64 / 1024.0, report.samples,
+                        report.chunks_reencoded, mb(report.size_before), mb(report.size_after)
+                    ),
+                    (note, _) => println!("⏭️ {}: {}", report.class, note.as_deref().unwrap_or_default()),
+                }
+            }
+            if reports.is_empty() {
+                println!("No DDS or buffer chunks to train on");
+            }
+            println!("\nTime: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
+        Commands::Recompress { archive, level, codec } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            if let Some(codec) = codec {
+                arch.set_codec(codec.into())?;
+            }
+            
+            println!("Recompressing chunks: {} level {}...", arch.config().codec.name(), level);
+            let report = arch.recompress(level)?;
+            
+            println!("✅ Complete{}", if report.resumed { " (resumed)" } else { "" });
+            println!("   Re-encoded chunks: {} / {}", report.chunks_reencoded, report.chunks);
+            println!("   Size: {:.2} MB → {:.2} MB", mb(report.size_before), mb(report.size_after));
+            println!("   Saved: {:.2} MB", (report.size_before as f64 - report.size_after as f64) / 1024.0 / 1024.0);
+            if arch.backend_name() == "pack" {
+                println!("   Run gc to reclaim the replaced pack data");
+            }
+            println!("   Time: {:.2}s", start.elapsed().as_secs_f64());
         }
         
-        Commands::Batch { mods_dir, archive } => {
+        Commands::Rechunk { archive, size, cdc, memory_mb } => {
             let start = Instant::now();
             let mut arch = ModArchive::open(&archive)?;
+            arch.set_memory_limit(memory_mb * 1024 * 1024);
+            let strategy = match (cdc, size) {
+                (Some(avg_size), _) => ChunkingStrategy::content_defined(avg_size),
+                (None, Some(size)) => ChunkingStrategy::Fixed { size },
+                (None, None) => unreachable!("clap requires --size or --cdc"),
+            };
+            
+            println!("Re-chunking mods: {:?}...", strategy);
+            let report = arch.rechunk(strategy)?;
+            
+            println!("✅ Complete");
+            println!("   Rewritten mods: {} ({} files), {} already up to date",
+                report.mods_rewritten, report.files_rechunked, report.mods_skipped);
+            println!("   Chunk storage: {:.2} MB → {:.2} MB", mb(report.size_before), mb(report.size_after));
+            println!("   Saved: {:.2} MB", (report.size_before as f64 - report.size_after as f64) / 1024.0 / 1024.0);
+            println!("   Time: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
+        Commands::Rehash { archive, to } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            let target: HashAlgorithm = to.into();
+            
+            println!("Re-hashing chunks: {} → {}...", arch.config().hash_algorithm.name(), target.name());
+            let report = arch.rehash(target)?;
+            
+            println!("✅ Complete");
+            println!("   Re-hashed chunks: {}", report.chunks);
+            println!("   Rewritten manifests: {} ({} files verified)", report.manifests, report.files_verified);
+            println!("   Time: {:.2}s", start.elapsed().as_secs_f64());
+        }
+        
+        Commands::Batch { mods_dir, archive, on_exists, cdc, memory_mb, codec, jobs } => {
+            let start = Instant::now();
+            let mut arch = ModArchive::open(&archive)?;
+            if let Some(avg_size) = cdc {
+                arch.set_chunking(ChunkingStrategy::content_defined(avg_size))?;
+            }
+            arch.set_memory_limit(memory_mb * 1024 * 1024);
+            if let Some(codec) = codec {
+                arch.set_codec(codec.into())?;
+            }
             
             let entries: Vec<_> = std::fs::read_dir(&mods_dir)?
                 .filter_map(|e| e.ok())
@@ -180,15 +793,26 @@ fn main() -> Result<()> {
             let mut success = 0;
             let mut failed = 0;
             
-            for entry in entries {
-                let mod_path = entry.path();
-                let mod_name = mod_path.file_name().unwrap().to_string_lossy();
+            let options = ArchiveOptions {
+                on_existing: on_exists.into(),
+                ..Default::default()
+            };
+            let mods: Vec<_> = entries.iter()
+                .map(|entry| (entry.path(), options.clone()))
+                .collect();
+            let results = arch.archive_many(&mods, jobs)?;
+            
+            for (entry, result) in entries.iter().zip(results) {
+                let mod_name = entry.file_name().to_string_lossy().to_string();
                 
                 print!("Archiving: {}... ", mod_name);
                 
-                match arch.archive_mod(&mod_path, None, None) {
-                    Ok(_) => {
+                match result {
+                    Ok(report) => {
                         println!("✅");
+                        for file in report.warnings() {
+                            println!("   ⚠️ {}: {}", file.path, file.note.as_deref().unwrap_or_default());
+                        }
                         success += 1;
                     }
                     Err(e) => {
@@ -210,3 +834,7 @@ fn main() -> Result<()> {
     
     Ok(())
 }
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0
+}
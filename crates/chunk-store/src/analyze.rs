@@ -0,0 +1,231 @@
+//! 导入前的去重分析
+//!
+//! `analyze` 按存储当前的配置分块并计算输入的 hash，但不写入任何数据：
+//! 统计已在存储中的字节数、与每个已归档 mod 重叠的字节数，以及导入后预计新增的存储大小。
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::archive::{is_preserved, ModArchive};
+use crate::buffer::load_strides;
+use crate::store::StoreError;
+
+/// 与输入重叠的已归档 mod
+#[derive(Debug, Clone)]
+pub struct ModOverlap {
+    pub id: String,
+    pub name: String,
+    /// 输入中该 mod 当前版本也引用的数据大小
+    pub shared_size: u64,
+    /// `shared_size` 占输入分块数据的比例
+    pub ratio: f64,
+}
+
+/// 去重分析结果
+#[derive(Debug, Default)]
+pub struct AnalyzeReport {
+    /// 需要分块的文件数
+    pub files: usize,
+    pub preserved_files: usize,
+    /// 输入的总大小（含保留文件）
+    pub original_size: u64,
+    /// 需要分块的数据大小
+    pub chunked_size: u64,
+    /// 输入中不同的块数
+    pub unique_chunks: usize,
+    /// 分块数据中已在存储中的大小（重复出现的块按次数计）
+    pub existing_size: u64,
+    /// 导入后预计新增的存储大小：新块压缩后的大小加保留文件的大小
+    pub projected_size: u64,
+    /// 与输入有重叠的 mod，按重叠大小降序
+    pub overlaps: Vec<ModOverlap>,
+}
+
+impl AnalyzeReport {
+    /// 分块数据中已在存储中的比例
+    pub fn existing_ratio(&self) -> f64 {
+        ratio(self.existing_size, self.chunked_size)
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// 输入中的块：原始大小、压缩后大小和出现次数
+#[derive(Default)]
+struct InputChunk {
+    original_size: u64,
+    stored_size: u64,
+    occurrences: u64,
+}
+
+impl InputChunk {
+    fn input_size(&self) -> u64 {
+        self.original_size * self.occurrences
+    }
+}
+
+impl ModArchive {
+    /// 分析导入 `path`（mod 目录或单个文件）的去重效果，不修改存储
+    ///
+    /// 文件按与归档相同的规则分块和压缩，每批读取的数据不超过内存上限的四分之一。
+    pub fn analyze(&self, path: &Path) -> Result<AnalyzeReport, StoreError> {
+        let root = if path.is_file() { path.parent().unwrap_or(Path::new("")) } else { path };
+        let mut report = AnalyzeReport::default();
+        let mut preserved = Vec::new();
+        let mut sources: Vec<(String, u64)> = Vec::new();
+        for entry in WalkDir::new(path) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry.path().strip_prefix(root)
+                .map_err(|_| StoreError::InvalidPath(entry.path().display().to_string()))?
+                .to_string_lossy()
+                .to_string();
+            let size = entry.metadata().map_err(std::io::Error::from)?.len();
+            report.original_size += size;
+            
+            let ext = entry.path().extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+            if is_preserved(&ext) {
+                // 保留文件原样复制，不参与去重
+                report.projected_size += size;
+                preserved.push(relative_path);
+            } else {
+                report.chunked_size += size;
+                sources.push((relative_path, size));
+            }
+        }
+        report.files = sources.len();
+        report.preserved_files = preserved.len();
+        
+        let strides = load_strides(root, &preserved);
+        let chunker = self.chunker()?;
+        let budget = (self.memory_limit / 4).max(1) as u64;
+        let mut input: HashMap<u128, InputChunk> = HashMap::new();
+        let mut start = 0;
+        while start < sources.len() {
+            let mut end = start + 1;
+            let mut group_size = sources[start].1;
+            while end < sources.len() && group_size + sources[end].1 <= budget {
+                group_size += sources[end].1;
+                end += 1;
+            }
+            
+            let chunked = sources[start..end].par_iter()
+                .map(|(relative_path, _)| {
+                    let data = fs::read(root.join(relative_path))?;
+                    let ext = Path::new(relative_path).extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or("")
+                        .to_lowercase();
+                    let (_, _, prepared) = chunker.chunk_file(relative_path, &ext, &data, &strides)?;
+                    Ok(prepared.into_iter()
+                        .map(|c| (c.hash, c.original_size as u64, c.compressed.len() as u64))
+                        .collect::<Vec<_>>())
+                })
+                .collect::<Result<Vec<_>, StoreError>>()?;
+            for (hash, original_size, stored_size) in chunked.into_iter().flatten() {
+                let chunk = input.entry(hash).or_default();
+                chunk.original_size = original_size;
+                chunk.stored_size = stored_size;
+                chunk.occurrences += 1;
+            }
+            start = end;
+        }
+        report.unique_chunks = input.len();
+        
+        // 被引用的块才算已存储，引用计数为 0 的块随时可能被回收
+        let mut existing = HashSet::new();
+        self.store.chunks.for_each(&mut |entry| {
+            if entry.ref_count > 0 && input.contains_key(&entry.hash) {
+                existing.insert(entry.hash);
+            }
+            Ok(())
+        })?;
+        for (hash, chunk) in &input {
+            if existing.contains(hash) {
+                report.existing_size += chunk.input_size();
+            } else {
+                report.projected_size += chunk.stored_size;
+            }
+        }
+        
+        for (id, name, _) in self.list_mods()? {
+            let Some(manifest) = self.load_manifest(&id)? else {
+                continue;
+            };
+            let refs: HashSet<u128> = manifest.chunk_refs()?.into_iter().collect();
+            let shared_size: u64 = refs.iter()
+                .filter_map(|hash| input.get(hash))
+                .map(InputChunk::input_size)
+                .sum();
+            if shared_size > 0 {
+                report.overlaps.push(ModOverlap {
+                    id,
+                    name,
+                    shared_size,
+                    ratio: ratio(shared_size, report.chunked_size),
+                });
+            }
+        }
+        report.overlaps.sort_by(|a, b| b.shared_size.cmp(&a.shared_size).then_with(|| a.id.cmp(&b.id)));
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_analyze_without_writing() {
+        let store = tempdir().unwrap();
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        let shared: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let new: Vec<u8> = (0..20_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        fs::write(first.path().join("a.dat"), &shared).unwrap();
+        fs::write(second.path().join("copy.dat"), &shared).unwrap();
+        fs::write(second.path().join("new.dat"), &new).unwrap();
+        fs::write(second.path().join("mod.ini"), "[mod]").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(first.path(), Some("a"), None).unwrap();
+        archive.archive_mod(first.path(), Some("b"), None).unwrap();
+        let before = archive.get_stats().unwrap();
+        
+        let report = archive.analyze(second.path()).unwrap();
+        assert_eq!((report.files, report.preserved_files), (2, 1));
+        assert_eq!(report.chunked_size, 60_000);
+        assert_eq!(report.existing_size, 40_000);
+        assert_eq!(report.overlaps.iter().map(|o| o.id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert!((report.overlaps[0].ratio - 40_000.0 / 60_000.0).abs() < 1e-9);
+        
+        // 没有写入任何块，也没有留下暂存目录
+        let after = archive.get_stats().unwrap();
+        assert_eq!(after.unique_chunks, before.unique_chunks);
+        let staging = fs::read_dir(store.path().join("staging"));
+        assert!(staging.map_or(true, |mut entries| entries.next().is_none()));
+        
+        // 预计大小与实际归档新增的大小一致
+        archive.archive_mod(second.path(), Some("c"), None).unwrap();
+        let archived = archive.get_stats().unwrap();
+        assert_eq!(
+            archived.chunks.stored_size - before.chunks.stored_size + "[mod]".len() as u64,
+            report.projected_size
+        );
+        
+        let single = archive.analyze(&second.path().join("new.dat")).unwrap();
+        assert_eq!((single.files, single.existing_size), (1, 20_000));
+    }
+}
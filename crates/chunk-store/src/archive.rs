@@ -1,32 +1,56 @@
 //! Mod 归档模块
 
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
 use walkdir::WalkDir;
 use serde::{Serialize, Deserialize};
 
-use crate::chunk::{chunk_data, prepare_chunks_parallel, ChunkConfig};
-use crate::dds::{parse_dds_header, rebuild_dds, DdsMetadata};
-use crate::store::{ChunkStore, StoreError, StoreStats};
+use crate::chunk::{
+    decompress_chunk, prepare_chunks_encoded, ChunkConfig, ChunkDictionary, ChunkingStrategy, Codec, HashAlgorithm,
+    PreparedChunk,
+};
+use crate::dict::dds_class;
+use crate::buffer::{buffer_strategy, load_strides};
+use crate::dds::{parse_dds_header, rebuild_dds, split_payload, DdsMetadata};
+use crate::fsck::FsckReport;
+use crate::journal::{FileOp, Staging};
+use crate::stats::StoreStats;
+use crate::backend::ChunkBackend;
+use crate::store::{chrono_timestamp, BackendKind, CachedFile, ChunkStore, ModCommit, StoreError};
 
 /// 文件清单
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileManifest {
     pub path: String,
     pub original_size: u64,
     pub chunks: Vec<String>, // hash 的十六进制表示
+    /// 整个文件的校验和，算法与块 hash 相同（旧版清单没有）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dds_metadata: Option<DdsMetadataSerde>,
+    /// 文件类型，`None` 表示整体分块存储的通用文件
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file_type: Option<String>,
+    /// 分块策略（旧版清单没有，均为固定 4KB 分块）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkingStrategy>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DdsMetadataSerde {
     pub header_size: usize,
     pub width: u32,
     pub height: u32,
     pub format: String,
+    /// 原始文件头所在块的 hash（与数据块一样去重存储）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_chunk: Option<String>,
 }
 
 impl From<&DdsMetadata> for DdsMetadataSerde {
@@ -36,255 +60,1232 @@ impl From<&DdsMetadata> for DdsMetadataSerde {
             width: m.width,
             height: m.height,
             format: m.format.clone(),
+            header_chunk: None,
         }
     }
 }
 
 /// Mod 清单
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModManifest {
     pub id: String,
     pub name: String,
     pub source_path: String,
+    /// 块 hash 与文件校验和使用的算法（旧清单没有记录，为 xxh3-128）
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
     pub files: Vec<FileManifest>,
     pub preserved_files: Vec<String>,
+    /// 空目录（相对路径），解压时重建
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub empty_dirs: Vec<String>,
     pub original_size: u64,
     pub stored_size: u64,
     pub created_at: i64,
 }
 
+impl FileManifest {
+    /// 是否为旧版整体压缩存储的 buf/ib 文件（`compressed/*.zst`，没有分块策略）
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.file_type.as_deref(), Some("buf") | Some("ib")) && self.chunking.is_none()
+    }
+}
+
+impl ModManifest {
+    /// 清单引用的所有块（按出现次数计，含 DDS 文件头）
+    pub fn chunk_refs(&self) -> Result<Vec<u128>, StoreError> {
+        let mut hashes = Vec::new();
+        for file in self.files.iter().filter(|f| !f.is_compressed()) {
+            let header_chunk = file.dds_metadata.as_ref().and_then(|m| m.header_chunk.as_ref());
+            for hash_str in header_chunk.into_iter().chain(&file.chunks) {
+                hashes.push(parse_hash(hash_str)?);
+            }
+        }
+        Ok(hashes)
+    }
+    
+    /// 清单引用的旧版 buf/ib 压缩文件 id
+    pub fn compressed_ids(&self) -> impl Iterator<Item = &str> {
+        self.files.iter()
+            .filter(|f| f.is_compressed())
+            .filter_map(|f| f.chunks.first().map(String::as_str))
+    }
+}
+
+/// 文件的归档方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHandling {
+    /// DDS 纹理：文件头 + 数据分块
+    Dds,
+    /// buf/ib：分块存储，已知元素大小时按元素对齐
+    Buffer,
+    /// 原样保留（ini、预览图等）
+    Preserved,
+    /// 通用文件：整体分块
+    Generic,
+    /// 未归档（符号链接等非普通文件）
+    Skipped,
+}
+
+/// 单个文件的归档结果
+#[derive(Debug, Clone)]
+pub struct ArchivedFile {
+    pub path: String,
+    pub size: u64,
+    pub handling: FileHandling,
+    /// 附加说明（例如 DDS 头无法解析而按通用文件存储）
+    pub note: Option<String>,
+}
+
+/// 归档报告
+#[derive(Debug)]
+pub struct ArchiveReport {
+    pub manifest: ModManifest,
+    pub files: Vec<ArchivedFile>,
+    /// 旧清单被保留为历史版本时的版本号
+    pub version: Option<i64>,
+    /// 与上次归档相比的变化
+    pub changes: ArchiveChanges,
+}
+
+/// 与同一 mod 上次归档相比的文件变化（首次归档时所有文件都是新增）
+#[derive(Debug, Default)]
+pub struct ArchiveChanges {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+    /// 大小和修改时间未变、没有重新读取的分块文件数
+    pub reused: usize,
+}
+
+impl ArchiveChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+    
+    /// 按路径和校验和比较上次与本次归档的文件
+    fn compare(previous: &HashMap<String, Option<String>>, current: &[CachedFile], reused: usize) -> Self {
+        let mut changes = Self { reused, ..Self::default() };
+        for file in current {
+            match previous.get(&file.path) {
+                Some(old) if old.is_some() && *old == file.checksum => changes.unchanged += 1,
+                Some(_) => changes.modified.push(file.path.clone()),
+                None => changes.added.push(file.path.clone()),
+            }
+        }
+        let current: HashSet<&str> = current.iter().map(|f| f.path.as_str()).collect();
+        changes.removed = previous.keys()
+            .filter(|path| !current.contains(path.as_str()))
+            .cloned()
+            .collect();
+        changes.removed.sort();
+        changes
+    }
+}
+
+impl ArchiveReport {
+    /// 按归档方式统计文件数
+    pub fn count(&self, handling: FileHandling) -> usize {
+        self.files.iter().filter(|f| f.handling == handling).count()
+    }
+    
+    /// 带说明的文件（需要提示用户）
+    pub fn warnings(&self) -> impl Iterator<Item = &ArchivedFile> {
+        self.files.iter().filter(|f| f.note.is_some())
+    }
+}
+
+/// mod id 已存在时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnExisting {
+    /// 替换旧清单，并释放旧清单的块引用
+    #[default]
+    Replace,
+    /// 拒绝归档，返回 `StoreError::ModExists`
+    Reject,
+    /// 旧清单保留为历史版本（块引用随之保留）
+    Version,
+}
+
+/// 提取时输出文件的写入方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtractMode {
+    /// 每个文件写入完整的副本
+    #[default]
+    Copy,
+    /// 每种文件内容只在提取缓存中还原一次，输出为指向缓存的硬链接
+    /// （不能硬链接时改用 reflink 或复制）。硬链接与缓存共享数据，因此是只读的；
+    /// 缓存文件在链接前按校验和检查，被改动时重新还原
+    Hardlink,
+    /// 同上，输出为 reflink（写时复制，修改输出不影响缓存），文件系统不支持时复制
+    Reflink,
+}
+
+/// 归档选项
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+    /// Mod ID（默认为目录名）
+    pub id: Option<String>,
+    /// Mod 名称（默认为 ID）
+    pub name: Option<String>,
+    pub on_existing: OnExisting,
+}
+
+/// 已分块、等待提交的 mod
+struct PendingMod {
+    id: String,
+    name: String,
+    source_path: PathBuf,
+    files: Vec<FileManifest>,
+    preserved_files: Vec<String>,
+    empty_dirs: Vec<String>,
+    original_size: u64,
+    /// 各批新写入的压缩字节数
+    stored_size: u64,
+    /// 沿用旧清单条目的文件的块引用
+    reused: Vec<u128>,
+    cache: Vec<CachedFile>,
+}
+
+/// 正在归档的 mod
+struct ModJob {
+    id: String,
+    name: String,
+    source_path: PathBuf,
+    on_existing: OnExisting,
+    preserved_files: Vec<String>,
+    empty_dirs: Vec<String>,
+    report_files: Vec<ArchivedFile>,
+    original_size: u64,
+    staging: Staging,
+    /// 尚未写入的分块文件数
+    remaining: usize,
+    /// (在归档报告中的位置, 清单条目)
+    files: Vec<(usize, FileManifest)>,
+    stored_size: u64,
+    /// 未改动的文件沿用的块引用
+    reused: Vec<u128>,
+    reused_files: usize,
+    /// 本次归档的文件缓存记录，分块文件的校验和在提交时填入
+    cache: Vec<CachedFile>,
+    /// 上次归档的文件及其校验和
+    previous: HashMap<String, Option<String>>,
+    /// 第一个错误，之后的文件不再写入
+    error: Option<StoreError>,
+}
+
+/// 已扫描的 mod：任务状态、需要分块的文件和 buf/ib 元素大小
+struct ScannedMod {
+    job: ModJob,
+    sources: Vec<SourceFile>,
+    strides: HashMap<String, usize>,
+}
+
+/// 等待分块的文件
+struct SourceFile {
+    relative_path: String,
+    path: PathBuf,
+    size: u64,
+    ext: String,
+    /// 在归档报告中的位置
+    report: usize,
+}
+
+/// 已分块的文件
+struct ProcessedFile {
+    index: usize,
+    report: ArchivedFile,
+    manifest: FileManifest,
+}
+
+/// 工作线程处理一批文件的结果：(mod 序号, 文件与块)
+type ProcessedBatch = Vec<(usize, Result<(ProcessedFile, Vec<PreparedChunk>), StoreError>)>;
+
+/// 分块和压缩文件所需的只读状态，由工作线程共享
+pub(crate) struct FileChunker {
+    config: ChunkConfig,
+    dictionaries: HashMap<String, ChunkDictionary>,
+}
+
+impl FileChunker {
+    /// 读取文件并分块压缩
+    fn process(&self, file: &SourceFile, strides: &HashMap<String, usize>) -> Result<(ProcessedFile, Vec<PreparedChunk>), StoreError> {
+        let data = fs::read(&file.path)?;
+        let (report, manifest, prepared) = self.chunk_file(&file.relative_path, &file.ext, &data, strides)?;
+        Ok((ProcessedFile { index: file.report, report, manifest }, prepared))
+    }
+    
+    /// 按扩展名选择分块方式并压缩文件数据（DDS 文件头单独作为一个块存储）
+    pub(crate) fn chunk_file(
+        &self,
+        relative_path: &str,
+        ext: &str,
+        data: &[u8],
+        strides: &HashMap<String, usize>,
+    ) -> Result<(ArchivedFile, FileManifest, Vec<PreparedChunk>), StoreError> {
+        let mut report = ArchivedFile {
+            path: relative_path.to_string(),
+            size: data.len() as u64,
+            handling: FileHandling::Generic,
+            note: None,
+        };
+        
+        let dds = match ext {
+            "dds" => {
+                let metadata = parse_dds_header(data);
+                match metadata {
+                    Some(_) => report.handling = FileHandling::Dds,
+                    None => report.note = Some("invalid DDS header, stored as generic data".to_string()),
+                }
+                metadata
+            }
+            _ => None,
+        };
+        let buffer = match ext {
+            "buf" | "ib" => {
+                report.handling = FileHandling::Buffer;
+                Some(ext.to_string())
+            }
+            _ => None,
+        };
+        
+        let mut pieces: Vec<(&[u8], Option<&ChunkDictionary>)> = Vec::new();
+        let payload = match &dds {
+            Some(metadata) => {
+                pieces.push((&data[..metadata.header_size], None));
+                &data[metadata.header_size..]
+            }
+            None => data,
+        };
+        let mip_aligned = dds.as_ref()
+            .filter(|_| self.config.dds_aware)
+            .and_then(|metadata| split_payload(metadata, payload, self.config.chunk_size()));
+        let (strategy, chunks) = match mip_aligned {
+            Some(chunks) => (ChunkingStrategy::MipAligned { size: self.config.chunk_size() }, chunks),
+            None => {
+                let strategy = match &buffer {
+                    Some(_) => buffer_strategy(strides, relative_path, self.config.strategy),
+                    None => self.config.strategy,
+                };
+                (strategy, strategy.split(payload))
+            }
+        };
+        let class = match (&dds, &buffer) {
+            (Some(metadata), _) => Some(dds_class(&metadata.format)),
+            (None, Some(ext)) => Some(ext.clone()),
+            (None, None) => None,
+        };
+        let dict = class.and_then(|c| self.dictionaries.get(&c));
+        pieces.extend(chunks.into_iter().map(|c| (c, dict)));
+        
+        let prepared = prepare_chunks_encoded(
+            pieces,
+            self.config.codec,
+            self.config.compression_level,
+            self.config.hash_algorithm,
+        )?;
+        let header = dds.is_some().then(|| format!("{:032x}", prepared[0].hash));
+        let hashes: Vec<String> = prepared[usize::from(dds.is_some())..]
+            .iter()
+            .map(|c| format!("{:032x}", c.hash))
+            .collect();
+        
+        let dds_metadata = dds.as_ref().map(|metadata| {
+            let mut serde = DdsMetadataSerde::from(metadata);
+            serde.header_chunk = header;
+            serde
+        });
+        let file_type = match &dds_metadata {
+            Some(_) => Some("dds".to_string()),
+            None => buffer,
+        };
+        
+        let manifest = FileManifest {
+            path: relative_path.to_string(),
+            original_size: data.len() as u64,
+            chunks: hashes,
+            checksum: Some(format!("{:032x}", self.config.hash_algorithm.hash(data))),
+            dds_metadata,
+            file_type,
+            chunking: Some(strategy),
+        };
+        Ok((report, manifest, prepared))
+    }
+}
+
+/// 提取时单个文件的还原方式
+pub(crate) struct ExtractPlan<'a> {
+    pub path: &'a str,
+    pub original_size: u64,
+    pub checksum: Option<&'a str>,
+    source: ExtractSource,
+    /// 先还原到提取缓存中的路径，写入前按该算法校验内容
+    cache: Option<(PathBuf, HashAlgorithm)>,
+}
+
+enum ExtractSource {
+    /// 由块拼接，DDS 文件额外带有文件头
+    Chunks { hashes: Vec<u128>, dds: Option<(DdsMetadata, DdsHeader)> },
+    /// 旧版整体压缩的 buf/ib 文件
+    Compressed(PathBuf),
+}
+
+enum DdsHeader {
+    Chunk(u128),
+    /// 旧版清单从原始文件读取文件头
+    Source(PathBuf),
+}
+
+impl ExtractPlan<'_> {
+    /// 文件引用的块（含 DDS 文件头）
+    pub fn chunk_refs(&self) -> impl Iterator<Item = u128> + '_ {
+        let (header, hashes) = match &self.source {
+            ExtractSource::Chunks { hashes, dds } => match dds {
+                Some((_, DdsHeader::Chunk(hash))) => (Some(*hash), &hashes[..]),
+                _ => (None, &hashes[..]),
+            },
+            ExtractSource::Compressed(_) => (None, &[][..]),
+        };
+        header.into_iter().chain(hashes.iter().copied())
+    }
+    
+    /// 还原文件内容并写入输出目录（使用提取缓存时写入缓存后链接到输出目录）
+    fn write<'c>(
+        &self,
+        output_path: &Path,
+        mode: ExtractMode,
+        chunk: impl Fn(&u128) -> &'c [u8],
+    ) -> Result<(), StoreError> {
+        let file_path = output_path.join(self.path);
+        let data = self.assemble(chunk)?;
+        if let Some((cached, algorithm)) = &self.cache {
+            // 缓存按内容共享，内容与校验和不符时不能写入
+            if self.checksum != Some(&format!("{:032x}", algorithm.hash(&data))) {
+                return Err(StoreError::CorruptManifest(format!("checksum mismatch: {}", self.path)));
+            }
+            write_atomic(cached, &data)?;
+            link_cached(cached, &file_path, mode)?;
+            return Ok(());
+        }
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        remove_existing(&file_path)?;
+        fs::write(&file_path, data)?;
+        Ok(())
+    }
+    
+    /// 由已读取的块还原文件内容
+    pub fn assemble<'c>(&self, chunk: impl Fn(&u128) -> &'c [u8]) -> Result<Vec<u8>, StoreError> {
+        let data = match &self.source {
+            ExtractSource::Chunks { hashes, dds: None } => {
+                hashes.iter().map(&chunk).collect::<Vec<_>>().concat()
+            }
+            ExtractSource::Chunks { hashes, dds: Some((metadata, header)) } => {
+                let header = match header {
+                    DdsHeader::Chunk(hash) => chunk(hash).to_vec(),
+                    DdsHeader::Source(path) => {
+                        let data = fs::read(path)?;
+                        data[..metadata.header_size.min(data.len())].to_vec()
+                    }
+                };
+                if header.len() != metadata.header_size {
+                    return Err(StoreError::CorruptManifest(format!(
+                        "DDS header of {} is {} bytes, expected {}",
+                        self.path, header.len(), metadata.header_size
+                    )));
+                }
+                let chunks: Vec<&[u8]> = hashes.iter().map(&chunk).collect();
+                rebuild_dds(metadata, &header, &chunks)
+            }
+            ExtractSource::Compressed(path) => {
+                let compressed = fs::read(path)?;
+                decompress_chunk(&compressed, self.original_size as usize)?
+            }
+        };
+        Ok(data)
+    }
+}
+
+/// 按原始大小把文件分成不超过 `budget` 的连续分组（超过该大小的文件单独成组）
+pub(crate) fn size_groups(plans: &[ExtractPlan], budget: u64) -> Vec<std::ops::Range<usize>> {
+    let mut groups: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut group_size = 0;
+    for (index, plan) in plans.iter().enumerate() {
+        match groups.last_mut() {
+            Some(group) if group_size + plan.original_size <= budget => {
+                group_size += plan.original_size;
+                group.end = index + 1;
+            }
+            _ => {
+                group_size = plan.original_size;
+                groups.push(index..index + 1);
+            }
+        }
+    }
+    groups
+}
+
+/// 提取缓存临时文件的序号
+static CACHE_TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 写入只读的临时文件后重命名，中断时不会留下不完整的缓存文件
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        CACHE_TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp, data)?;
+    let mut permissions = fs::metadata(&tmp)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&tmp, permissions)?;
+    // 替换内容不符的旧缓存文件（Windows 不能重命名覆盖只读文件）
+    remove_existing(path)?;
+    fs::rename(&tmp, path)
+}
+
+/// 缓存中的文件与校验和一致时才能直接链接：硬链接到输出目录后仍可能被原地修改
+fn cache_valid(cached: &Path, size: u64, algorithm: HashAlgorithm, checksum: &str) -> bool {
+    let Ok(mut file) = fs::File::open(cached) else {
+        return false;
+    };
+    if !file.metadata().is_ok_and(|m| m.len() == size) {
+        return false;
+    }
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return false,
+        }
+    }
+    format!("{:032x}", hasher.finish()) == checksum
+}
+
+/// 把缓存中的文件链接到输出路径：硬链接失败时（例如跨文件系统）尝试 reflink，再退回复制
+fn link_cached(cached: &Path, output: &Path, mode: ExtractMode) -> std::io::Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    remove_existing(output)?;
+    if mode == ExtractMode::Hardlink && fs::hard_link(cached, output).is_ok() {
+        return Ok(());
+    }
+    // 复制得到的文件不共享缓存，不需要继承缓存的只读属性
+    reflink_copy::reflink_or_copy(cached, output)?;
+    make_writable(output)
+}
+
+/// 删除已有的输出文件：它可能是指向提取缓存的硬链接，直接覆盖写入会改动缓存
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        // Windows 不能删除只读文件（缓存文件及其硬链接）
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            make_writable(path)?;
+            fs::remove_file(path)
+        }
+        result => result,
+    }
+}
+
+/// 去掉文件的只读属性（Unix 上只加回所有者的写权限）
+fn make_writable(path: &Path) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+/// 提取缓存目录（相对于存储根目录）
+const EXTRACT_CACHE_DIR: &str = "extract-cache";
+
+/// 默认的归档内存上限
+pub const DEFAULT_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
 /// Mod 归档管理器
 pub struct ModArchive {
-    store: ChunkStore,
-    config: ChunkConfig,
+    pub(crate) store: ChunkStore,
+    pub(crate) config: ChunkConfig,
+    /// 归档和提取时在内存中处理的数据上限（字节）
+    pub(crate) memory_limit: usize,
+    extract_mode: ExtractMode,
 }
 
 impl ModArchive {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
-        Ok(Self {
-            store: ChunkStore::open(path)?,
-            config: ChunkConfig::default(),
-        })
+        Self::with_store(ChunkStore::open(path)?)
+    }
+    
+    /// 使用指定的块后端创建归档
+    pub fn create<P: AsRef<Path>>(path: P, backend: BackendKind) -> Result<Self, StoreError> {
+        Self::with_store(ChunkStore::create(path, backend)?)
+    }
+    
+    /// 使用自定义的块后端打开归档
+    pub fn with_backend<P: AsRef<Path>>(path: P, backend: Box<dyn ChunkBackend>) -> Result<Self, StoreError> {
+        Self::with_store(ChunkStore::with_backend(path, backend)?)
+    }
+    
+    fn with_store(store: ChunkStore) -> Result<Self, StoreError> {
+        let config = store.chunk_config()?;
+        Ok(Self { store, config, memory_limit: DEFAULT_MEMORY_LIMIT, extract_mode: ExtractMode::Copy })
     }
     
-    /// 归档 mod
+    /// 之后归档的文件使用的分块策略（已归档的文件不受影响）
+    pub fn set_chunking(&mut self, strategy: ChunkingStrategy) -> Result<(), StoreError> {
+        strategy.validate()?;
+        self.config.strategy = strategy;
+        Ok(())
+    }
+    
+    /// 之后写入的块的默认编码（`Zstd`、`Lz4` 或 `Raw`）
+    pub fn set_codec(&mut self, codec: Codec) -> Result<(), StoreError> {
+        if let Codec::ZstdDict(_) = codec {
+            return Err(StoreError::InvalidConfig("dictionaries are selected per content class".to_string()));
+        }
+        self.config.codec = codec;
+        Ok(())
+    }
+    
+    /// 归档和提取时在内存中处理的数据上限（字节），峰值内存与 mod 大小无关
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+    }
+    
+    /// 之后提取时输出文件的写入方式
+    pub fn set_extract_mode(&mut self, mode: ExtractMode) {
+        self.extract_mode = mode;
+    }
+    
+    pub fn config(&self) -> &ChunkConfig {
+        &self.config
+    }
+    
+    /// 归档 mod（id 已存在时替换）
     pub fn archive_mod(
         &mut self,
         mod_path: &Path,
         mod_id: Option<&str>,
         mod_name: Option<&str>,
-    ) -> Result<ModManifest, StoreError> {
-        let id = mod_id
-            .map(String::from)
-            .unwrap_or_else(|| mod_path.file_name().unwrap().to_string_lossy().to_string());
-        let name = mod_name
-            .map(String::from)
+    ) -> Result<ArchiveReport, StoreError> {
+        self.archive_mod_with(mod_path, &ArchiveOptions {
+            id: mod_id.map(String::from),
+            name: mod_name.map(String::from),
+            ..Default::default()
+        })
+    }
+    
+    /// 按选项归档 mod
+    ///
+    /// 文件按内存上限分批：工作线程读取、分块并压缩一批文件时，上一批的块在当前线程写入存储。
+    /// 各批的块引用登记为未提交，与清单在同一事务中确认，失败时撤销。
+    pub fn archive_mod_with(
+        &mut self,
+        mod_path: &Path,
+        options: &ArchiveOptions,
+    ) -> Result<ArchiveReport, StoreError> {
+        let chunker = self.chunker()?;
+        let scanned = self.scan_mod(mod_path, options)?;
+        self.archive_scanned(vec![scanned], &chunker, None).remove(0)
+    }
+    
+    /// 并发归档多个 mod，返回与 `mods` 顺序相同的结果
+    ///
+    /// 所有 mod 的文件在 `jobs` 个工作线程上分块压缩（0 表示 CPU 核数），小 mod 也能占满所有核；
+    /// 块由当前线程统一写入，mod 按顺序逐个提交。单个 mod 失败不影响其他 mod。
+    pub fn archive_many(
+        &mut self,
+        mods: &[(PathBuf, ArchiveOptions)],
+        jobs: usize,
+    ) -> Result<Vec<Result<ArchiveReport, StoreError>>, StoreError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(|e| StoreError::InvalidConfig(e.to_string()))?;
+        let chunker = self.chunker()?;
+        
+        let mut results: Vec<Option<Result<ArchiveReport, StoreError>>> = mods.iter().map(|_| None).collect();
+        let mut scanned = Vec::new();
+        let mut slots = Vec::new();
+        for (slot, (mod_path, options)) in mods.iter().enumerate() {
+            match self.scan_mod(mod_path, options) {
+                Ok(s) => {
+                    scanned.push(s);
+                    slots.push(slot);
+                }
+                Err(e) => results[slot] = Some(Err(e)),
+            }
+        }
+        
+        let archived = self.archive_scanned(scanned, &chunker, Some(&pool));
+        for (slot, result) in slots.into_iter().zip(archived) {
+            results[slot] = Some(result);
+        }
+        Ok(results.into_iter().map(|r| r.expect("every mod has a result")).collect())
+    }
+    
+    /// 当前配置和各类别最新字典
+    pub(crate) fn chunker(&self) -> Result<FileChunker, StoreError> {
+        self.chunker_with(self.config.clone())
+    }
+    
+    /// 指定配置和各类别最新字典（按该配置的压缩级别）
+    pub(crate) fn chunker_with(&self, config: ChunkConfig) -> Result<FileChunker, StoreError> {
+        Ok(FileChunker {
+            dictionaries: self.store.latest_dictionaries(config.compression_level)?,
+            config,
+        })
+    }
+    
+    /// 遍历 mod 目录，区分保留文件和需要分块的文件，并创建暂存区
+    fn scan_mod(&self, mod_path: &Path, options: &ArchiveOptions) -> Result<ScannedMod, StoreError> {
+        let id = match &options.id {
+            Some(id) => id.clone(),
+            None => mod_path.file_name()
+                .ok_or_else(|| StoreError::InvalidPath(mod_path.display().to_string()))?
+                .to_string_lossy()
+                .to_string(),
+        };
+        let name = options.name.clone()
             .unwrap_or_else(|| id.clone());
         
-        let mut files = Vec::new();
-        let mut preserved_files = Vec::new();
-        let mut original_size = 0u64;
-        let mut stored_size = 0u64;
+        let existing = self.load_manifest(&id)?;
+        if options.on_existing == OnExisting::Reject && existing.is_some() {
+            return Err(StoreError::ModExists(id));
+        }
         
-        // 收集所有 DDS 文件
-        struct DdsFile {
-            relative_path: String,
-            data: Vec<u8>,
-            metadata: DdsMetadata,
+        // 上次归档的文件：大小和修改时间与缓存一致的分块文件直接沿用清单条目
+        let file_cache = self.store.load_file_cache(&id)?;
+        let mut previous: HashMap<String, Option<String>> = HashMap::new();
+        let mut previous_files: HashMap<&str, &FileManifest> = HashMap::new();
+        if let Some(old) = &existing {
+            for file in &old.files {
+                previous.insert(file.path.clone(), file.checksum.clone());
+                if !file.is_compressed() {
+                    previous_files.insert(&file.path, file);
+                }
+            }
+            for path in &old.preserved_files {
+                previous.insert(path.clone(), file_cache.get(path).and_then(|c| c.checksum.clone()));
+            }
         }
-        let mut dds_files: Vec<DdsFile> = Vec::new();
-        let mut other_files: Vec<(String, String, Vec<u8>)> = Vec::new();
         
-        for entry in WalkDir::new(mod_path).into_iter().filter_map(|e| e.ok()) {
+        let mut preserved_files = Vec::new();
+        let mut empty_dirs = Vec::new();
+        let mut report_files = Vec::new();
+        let mut original_size = 0u64;
+        let mut sources: Vec<SourceFile> = Vec::new();
+        let mut files = Vec::new();
+        let mut reused = Vec::new();
+        let mut cache = Vec::new();
+        
+        for entry in WalkDir::new(mod_path).min_depth(1) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            let path = entry.path();
+            let relative_path = path.strip_prefix(mod_path)
+                .map_err(|_| StoreError::InvalidPath(path.display().to_string()))?
+                .to_string_lossy()
+                .to_string();
+            
+            if entry.file_type().is_dir() {
+                if fs::read_dir(path)?.next().is_none() {
+                    empty_dirs.push(relative_path);
+                }
+                continue;
+            }
             if !entry.file_type().is_file() {
+                report_files.push(ArchivedFile {
+                    path: relative_path,
+                    size: 0,
+                    handling: FileHandling::Skipped,
+                    note: Some("not a regular file".to_string()),
+                });
                 continue;
             }
             
-            let path = entry.path();
-            let relative_path = path.strip_prefix(mod_path).unwrap().to_string_lossy().to_string();
             let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
-            
-            let data = fs::read(path)?;
-            original_size += data.len() as u64;
+            let metadata = entry.metadata().map_err(std::io::Error::from)?;
+            let size = metadata.len();
+            let mtime = modified_nanos(&metadata);
+            let cached = file_cache.get(&relative_path)
+                .filter(|c| mtime != 0 && c.size == size && c.mtime == mtime)
+                .and_then(|c| c.checksum.clone());
             
             match ext.as_str() {
-                "dds" => {
-                    if let Some(metadata) = parse_dds_header(&data) {
-                        dds_files.push(DdsFile { relative_path, data, metadata });
-                    }
+                ext if is_preserved(ext) => {
+                    // 保留文件每次都复制，校验和只用于变化报告
+                    let checksum = match cached {
+                        Some(checksum) => checksum,
+                        None => format!("{:032x}", self.config.hash_algorithm.hash(&fs::read(path)?)),
+                    };
+                    cache.push(CachedFile { path: relative_path.clone(), size, mtime, checksum: Some(checksum) });
+                    original_size += size;
+                    report_files.push(ArchivedFile {
+                        path: relative_path.clone(),
+                        size,
+                        handling: FileHandling::Preserved,
+                        note: None,
+                    });
+                    preserved_files.push(relative_path);
                 }
-                "buf" | "ib" => {
-                    other_files.push((relative_path, ext, data));
+                _ => {
+                    let unchanged = previous_files.get(relative_path.as_str())
+                        .filter(|old| cached.is_some() && old.checksum == cached);
+                    if let Some(old) = unchanged {
+                        original_size += old.original_size;
+                        reused.extend(old.dds_metadata.as_ref()
+                            .and_then(|m| m.header_chunk.as_deref())
+                            .into_iter()
+                            .chain(old.chunks.iter().map(String::as_str))
+                            .map(parse_hash)
+                            .collect::<Result<Vec<_>, _>>()?);
+                        report_files.push(ArchivedFile {
+                            path: relative_path.clone(),
+                            size,
+                            handling: match old.file_type.as_deref() {
+                                Some("dds") => FileHandling::Dds,
+                                Some(_) => FileHandling::Buffer,
+                                None => FileHandling::Generic,
+                            },
+                            note: None,
+                        });
+                        files.push((report_files.len() - 1, (*old).clone()));
+                        cache.push(CachedFile { path: relative_path, size, mtime, checksum: cached });
+                        continue;
+                    }
+                    
+                    // 处理完成后替换为实际的归档结果
+                    cache.push(CachedFile { path: relative_path.clone(), size, mtime, checksum: None });
+                    report_files.push(ArchivedFile {
+                        path: relative_path.clone(),
+                        size,
+                        handling: FileHandling::Generic,
+                        note: None,
+                    });
+                    sources.push(SourceFile {
+                        relative_path,
+                        path: path.to_path_buf(),
+                        size,
+                        ext,
+                        report: report_files.len() - 1,
+                    });
                 }
-                "ini" | "png" | "jpg" | "jpeg" | "webp" | "gif" | "txt" | "md" | "json" => {
-                    preserved_files.push(relative_path);
+            }
+        }
+        
+        let strides = load_strides(mod_path, &preserved_files);
+        // 所有新文件先写入暂存区，数据库提交后再移动到位
+        let staging = Staging::create(Path::new(self.store.base_path()))?;
+        let job = ModJob {
+            id,
+            name,
+            source_path: mod_path.to_path_buf(),
+            on_existing: options.on_existing,
+            preserved_files,
+            empty_dirs,
+            report_files,
+            original_size,
+            staging,
+            remaining: sources.len(),
+            reused_files: files.len(),
+            files,
+            stored_size: 0,
+            reused,
+            cache,
+            previous,
+            error: None,
+        };
+        Ok(ScannedMod { job, sources, strides })
+    }
+    
+    /// 分批分块已扫描的 mod 并写入块，每个 mod 的文件全部写入后按顺序提交
+    ///
+    /// 同一时间最多有三批数据在内存中（正在处理、等待写入、正在写入），
+    /// 每批读取的数据不超过内存上限的四分之一；单个文件整体读取，超过该大小时单独成批。
+    fn archive_scanned(
+        &mut self,
+        mods: Vec<ScannedMod>,
+        chunker: &FileChunker,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Vec<Result<ArchiveReport, StoreError>> {
+        let budget = (self.memory_limit / 4).max(1) as u64;
+        let mut jobs = VecDeque::with_capacity(mods.len());
+        let mut strides = Vec::with_capacity(mods.len());
+        let mut batches: Vec<Vec<(usize, SourceFile)>> = Vec::new();
+        let mut batch_size = 0;
+        for (index, scanned) in mods.into_iter().enumerate() {
+            for file in scanned.sources {
+                match batches.last_mut() {
+                    Some(batch) if batch_size + file.size <= budget => {
+                        batch_size += file.size;
+                        batch.push((index, file));
+                    }
+                    _ => {
+                        batch_size = file.size;
+                        batches.push(vec![(index, file)]);
+                    }
                 }
-                _ => {}
             }
+            jobs.push_back(scanned.job);
+            strides.push(scanned.strides);
         }
         
-        // 分块所有 DDS 文件
-        let mut all_chunks: Vec<&[u8]> = Vec::new();
-        let mut chunk_ranges: Vec<(usize, usize)> = Vec::new(); // (start, count)
+        let mut results = Vec::with_capacity(jobs.len());
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel::<ProcessedBatch>(1);
+            let strides = &strides;
+            scope.spawn(move || {
+                for batch in batches {
+                    let process = || batch.par_iter()
+                        .map(|(index, file)| (*index, chunker.process(file, &strides[*index])))
+                        .collect();
+                    let processed = match pool {
+                        Some(pool) => pool.install(process),
+                        None => process(),
+                    };
+                    if sender.send(processed).is_err() {
+                        break;
+                    }
+                }
+            });
+            
+            self.commit_ready(&mut jobs, &mut results);
+            for batch in receiver {
+                self.write_batch(&mut jobs, results.len(), batch);
+                self.commit_ready(&mut jobs, &mut results);
+            }
+        });
+        results
+    }
+    
+    /// 写入一批已分块的文件，`committed` 为已提交的 mod 数（`jobs` 第一项的序号）
+    fn write_batch(&mut self, jobs: &mut VecDeque<ModJob>, committed: usize, batch: ProcessedBatch) {
+        let mut chunks: BTreeMap<usize, Vec<PreparedChunk>> = BTreeMap::new();
+        for (index, result) in batch {
+            let job = &mut jobs[index - committed];
+            job.remaining -= 1;
+            match result {
+                Ok((file, prepared)) if job.error.is_none() => {
+                    job.original_size += file.manifest.original_size;
+                    job.report_files[file.index] = file.report;
+                    job.files.push((file.index, file.manifest));
+                    chunks.entry(index).or_default().extend(prepared);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    job.error.get_or_insert(e);
+                }
+            }
+        }
         
-        for dds in &dds_files {
-            let start = all_chunks.len();
-            let chunks = chunk_data(&dds.data[dds.metadata.header_size..], self.config.chunk_size);
-            let count = chunks.len();
-            all_chunks.extend(chunks);
-            chunk_ranges.push((start, count));
+        for (index, chunks) in chunks {
+            let job = &mut jobs[index - committed];
+            if job.error.is_some() {
+                continue;
+            }
+            match self.store.put_pending(job.staging.token(), &chunks) {
+                Ok(size) => job.stored_size += size,
+                Err(e) => job.error = Some(e),
+            }
         }
+    }
+    
+    /// 按顺序提交所有文件都已写入的 mod
+    fn commit_ready(&mut self, jobs: &mut VecDeque<ModJob>, results: &mut Vec<Result<ArchiveReport, StoreError>>) {
+        while jobs.front().is_some_and(|job| job.remaining == 0) {
+            let job = jobs.pop_front().expect("front checked above");
+            results.push(self.commit_job(job));
+        }
+    }
+    
+    /// 提交 mod；失败时丢弃暂存区并撤销已写入的块引用
+    fn commit_job(&mut self, job: ModJob) -> Result<ArchiveReport, StoreError> {
+        let ModJob {
+            id, name, source_path, on_existing, preserved_files, empty_dirs, report_files, original_size,
+            mut staging, mut files, stored_size, reused, reused_files, mut cache, previous, error, ..
+        } = job;
         
-        // 并行压缩所有块
-        let prepared = prepare_chunks_parallel(all_chunks, self.config.compression_level);
+        files.sort_by_key(|(index, _)| *index);
+        let files: Vec<FileManifest> = files.into_iter().map(|(_, file)| file).collect();
+        let checksums: HashMap<&str, &Option<String>> = files.iter()
+            .map(|f| (f.path.as_str(), &f.checksum))
+            .collect();
+        for entry in cache.iter_mut().filter(|c| c.checksum.is_none()) {
+            entry.checksum = checksums.get(entry.path.as_str()).and_then(|c| (*c).clone());
+        }
+        let changes = ArchiveChanges::compare(&previous, &cache, reused_files);
         
-        // 批量写入数据库
-        stored_size += self.store.store_chunks_batch(&prepared)?;
+        let result = match error {
+            Some(e) => Err(e),
+            // 同一批中可能有相同 id 的 mod，提交时重新读取旧清单
+            None => self.load_manifest(&id).and_then(|existing| {
+                if existing.is_some() && on_existing == OnExisting::Reject {
+                    return Err(StoreError::ModExists(id.clone()));
+                }
+                let pending = PendingMod {
+                    id: id.clone(),
+                    name,
+                    source_path,
+                    files,
+                    preserved_files,
+                    empty_dirs,
+                    original_size,
+                    stored_size,
+                    reused,
+                    cache,
+                };
+                self.stage_and_commit(&mut staging, pending, existing, on_existing)
+            }),
+        };
         
-        // 生成 DDS 文件清单
-        for (i, dds) in dds_files.iter().enumerate() {
-            let (start, count) = chunk_ranges[i];
-            let hashes: Vec<String> = prepared[start..start + count]
-                .iter()
-                .map(|c| format!("{:032x}", c.hash))
-                .collect();
-            
-            files.push(FileManifest {
-                path: dds.relative_path.clone(),
-                original_size: dds.data.len() as u64,
-                chunks: hashes,
-                dds_metadata: Some(DdsMetadataSerde::from(&dds.metadata)),
-                file_type: Some("dds".to_string()),
-            });
+        match result {
+            Ok((manifest, version)) => {
+                staging.finish_committed(&self.store.conn);
+                Ok(ArchiveReport { manifest, files: report_files, version, changes })
+            }
+            Err(e) => {
+                let token = staging.token().to_string();
+                staging.discard();
+                // 撤销失败时登记仍在，下次打开存储时撤销
+                let _ = self.store.rollback_pending(Some(&token));
+                Err(e)
+            }
         }
+    }
+    
+    /// 把保留文件写入暂存区，并在一个事务中提交块引用和清单
+    fn stage_and_commit(
+        &mut self,
+        staging: &mut Staging,
+        pending: PendingMod,
+        existing: Option<ModManifest>,
+        on_existing: OnExisting,
+    ) -> Result<(ModManifest, Option<i64>), StoreError> {
+        let PendingMod {
+            id, name, source_path, files, preserved_files, empty_dirs, original_size, stored_size, reused, cache,
+        } = pending;
         
-        // 处理 buf/ib 文件（压缩存储）
-        for (relative_path, ext, data) in other_files {
-            let compressed = zstd::bulk::compress(&data, self.config.compression_level)
-                .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-            
-            let file_id = format!("{}_{:016x}", id, crate::chunk::hash_chunk(&data) as u64);
-            let compressed_path = PathBuf::from(self.store.base_path())
-                .join("compressed")
-                .join(format!("{}.zst", file_id));
-            
-            fs::create_dir_all(compressed_path.parent().unwrap())?;
-            fs::write(&compressed_path, &compressed)?;
-            
-            stored_size += compressed.len() as u64;
-            
-            files.push(FileManifest {
-                path: relative_path,
-                original_size: data.len() as u64,
-                chunks: vec![file_id],
-                dds_metadata: None,
-                file_type: Some(ext),
-            });
+        // 旧的保留文件：作为历史版本时移到版本目录，否则随暂存区一起删除
+        let mods_dir = Path::new("mods").join(&id);
+        let mut version = None;
+        let mut released = Vec::new();
+        if let Some(old) = &existing {
+            if on_existing == OnExisting::Version {
+                let v = self.store.next_mod_version(&id)?;
+                staging.push(FileOp::Move {
+                    from: mods_dir.clone(),
+                    to: Path::new("versions").join(&id).join(v.to_string()),
+                });
+                version = Some(v);
+            } else {
+                released = old.chunk_refs()?;
+                staging.push(FileOp::Move {
+                    from: mods_dir.clone(),
+                    to: staging.dir().join("old"),
+                });
+            }
         }
         
         // 复制保留的文件
-        let mods_dir = PathBuf::from(self.store.base_path()).join("mods").join(&id);
         for relative_path in &preserved_files {
-            let src = mod_path.join(relative_path);
-            let dst = mods_dir.join(relative_path);
+            let src = source_path.join(relative_path);
+            let dst = staging.path("mods").join(relative_path);
             if let Some(parent) = dst.parent() {
                 fs::create_dir_all(parent)?;
             }
             fs::copy(&src, &dst)?;
         }
+        if !preserved_files.is_empty() {
+            staging.push(FileOp::Move {
+                from: staging.dir().join("mods"),
+                to: mods_dir,
+            });
+        }
         
         let manifest = ModManifest {
             id: id.clone(),
             name,
-            source_path: mod_path.to_string_lossy().to_string(),
+            source_path: source_path.to_string_lossy().to_string(),
+            hash_algorithm: self.config.hash_algorithm,
             files,
             preserved_files,
+            empty_dirs,
             original_size,
             stored_size,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as i64,
+            created_at: chrono_timestamp(),
         };
         
-        // 保存清单
-        let manifest_json = serde_json::to_string(&manifest)
-            .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-        self.store.save_mod(&manifest.id, &manifest.name, &manifest_json)?;
+        // 替换时删除不再被任何清单（含历史版本）引用的旧版压缩文件
+        if let (Some(old), None) = (&existing, version) {
+            let mut keep: HashSet<String> = manifest.compressed_ids().map(String::from).collect();
+            for (_, old_version) in self.load_versions(&id)? {
+                keep.extend(old_version.compressed_ids().map(String::from));
+            }
+            for file_id in old.compressed_ids().filter(|f| !keep.contains(*f)) {
+                staging.push(FileOp::Remove {
+                    path: Path::new("compressed").join(format!("{}.zst", file_id)),
+                });
+            }
+        }
+        
+        // 块引用确认、旧引用释放、清单和文件操作日志在同一事务中提交
+        let plan = staging.plan()?;
+        let commit = ModCommit {
+            id: &id,
+            name: &manifest.name,
+            reused: &reused,
+            released: &released,
+            keep_version: version,
+            journal: (staging.token(), &plan),
+            cache: &cache,
+            created_at: manifest.created_at,
+        };
+        let json = serde_json::to_string(&manifest)
+            .map_err(|e| StoreError::CorruptManifest(e.to_string()))?;
+        self.store.commit_mod(&commit, &json)?;
         
-        Ok(manifest)
+        Ok((manifest, version))
     }
     
     /// 解压 mod
     pub fn extract_mod(&self, mod_id: &str, output_path: &Path) -> Result<(), StoreError> {
-        let (_, manifest_json) = self.store.get_mod(mod_id)?
-            .ok_or_else(|| StoreError::ChunkNotFound(mod_id.to_string()))?;
+        let manifest = self.load_manifest(mod_id)?
+            .ok_or_else(|| StoreError::ModNotFound(mod_id.to_string()))?;
         
-        let manifest: ModManifest = serde_json::from_str(&manifest_json)
-            .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+        self.extract_manifest(&manifest, &self.preserved_dir(mod_id), output_path)
+    }
+    
+    /// 解压 mod 的历史版本
+    pub fn extract_version(&self, mod_id: &str, version: i64, output_path: &Path) -> Result<(), StoreError> {
+        let manifest_json = self.store.get_mod_version(mod_id, version)?
+            .ok_or_else(|| StoreError::ModNotFound(format!("{}@{}", mod_id, version)))?;
+        let manifest = self.read_manifest(mod_id, &manifest_json)?;
         
+        self.extract_manifest(&manifest, &self.version_dir(mod_id, version), output_path)
+    }
+    
+    /// 列出 mod 的历史版本 (version, created_at)
+    pub fn list_versions(&self, mod_id: &str) -> Result<Vec<(i64, i64)>, StoreError> {
+        self.store.list_mod_versions(mod_id)
+    }
+    
+    fn extract_manifest(
+        &self,
+        manifest: &ModManifest,
+        mods_dir: &Path,
+        output_path: &Path,
+    ) -> Result<(), StoreError> {
         fs::create_dir_all(output_path)?;
         
-        for file in &manifest.files {
-            let file_path = output_path.join(&file.path);
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
+        // 旧版压缩文件的路径在这里确定（`self` 不能跨线程共享）
+        let mut plans: Vec<ExtractPlan> = manifest.files.iter()
+            .map(|file| self.extract_plan(manifest, file))
+            .collect::<Result<_, _>>()?;
+        
+        // 提取缓存中已有且内容未被改动的文件直接链接，不读取块；同一内容只还原一次
+        let mode = self.extract_mode;
+        let mut linked: Vec<(PathBuf, &str)> = Vec::new();
+        if mode != ExtractMode::Copy {
+            let algorithm = manifest.hash_algorithm;
+            let mut entries: HashMap<PathBuf, (u64, &str)> = HashMap::new();
+            for plan in &plans {
+                if let Some(checksum) = plan.checksum {
+                    entries.entry(self.extract_cache_path(algorithm, checksum))
+                        .or_insert((plan.original_size, checksum));
+                }
             }
+            let valid: HashSet<&PathBuf> = entries.par_iter()
+                .filter(|(cached, (size, checksum))| cache_valid(cached, *size, algorithm, checksum))
+                .map(|(cached, _)| cached)
+                .collect();
             
-            match file.file_type.as_deref() {
-                Some("dds") => {
-                    // 读取原始文件头
-                    let original_path = PathBuf::from(&manifest.source_path).join(&file.path);
-                    let header = if original_path.exists() {
-                        let data = fs::read(&original_path)?;
-                        data[..file.dds_metadata.as_ref().unwrap().header_size].to_vec()
-                    } else {
-                        // 如果原始文件不存在，创建一个基本的 DDS 头
-                        vec![0u8; file.dds_metadata.as_ref().unwrap().header_size]
-                    };
-                    
-                    let hashes: Vec<u128> = file.chunks.iter()
-                        .map(|h| u128::from_str_radix(h, 16).unwrap())
-                        .collect();
-                    
-                    let chunks = self.store.read_chunks(&hashes)?;
-                    
-                    let metadata = DdsMetadata {
-                        header_size: file.dds_metadata.as_ref().unwrap().header_size,
-                        width: file.dds_metadata.as_ref().unwrap().width,
-                        height: file.dds_metadata.as_ref().unwrap().height,
-                        format: file.dds_metadata.as_ref().unwrap().format.clone(),
-                    };
-                    
-                    let dds_data = rebuild_dds(&metadata, &header, &chunks);
-                    fs::write(&file_path, dds_data)?;
+            let mut materializing = HashSet::new();
+            let mut pending = Vec::with_capacity(plans.len());
+            for mut plan in plans {
+                let Some(checksum) = plan.checksum else {
+                    pending.push(plan);
+                    continue;
+                };
+                let cached = self.extract_cache_path(algorithm, checksum);
+                if valid.contains(&cached) || !materializing.insert(cached.clone()) {
+                    linked.push((cached, plan.path));
+                } else {
+                    // 缺失或内容不符的缓存文件重新还原
+                    plan.cache = Some((cached, algorithm));
+                    pending.push(plan);
                 }
-                Some("buf") | Some("ib") => {
-                    let compressed_path = PathBuf::from(self.store.base_path())
-                        .join("compressed")
-                        .join(format!("{}.zst", &file.chunks[0]));
-                    
-                    let compressed = fs::read(&compressed_path)?;
-                    let data = zstd::bulk::decompress(&compressed, file.original_size as usize)
-                        .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-                    
-                    fs::write(&file_path, data)?;
+            }
+            plans = pending;
+        }
+        
+        let mut remaining: HashMap<u128, usize> = HashMap::new();
+        for hash in plans.iter().flat_map(ExtractPlan::chunk_refs) {
+            *remaining.entry(hash).or_insert(0) += 1;
+        }
+        
+        // 按原始大小分组：每组的块一次读取并解码，组内文件并行写入；
+        // 后续分组仍要用到的块保留在 `shared` 中，不重复读取
+        let budget = (self.memory_limit / 2).max(1) as u64;
+        let mut shared: HashMap<u128, Vec<u8>> = HashMap::new();
+        for range in size_groups(&plans, budget) {
+            let group = &plans[range];
+            let needed: Vec<u128> = group.iter()
+                .flat_map(ExtractPlan::chunk_refs)
+                .filter(|hash| !shared.contains_key(hash))
+                .collect();
+            let mut fetched = self.store.read_chunk_map(&needed)?;
+            
+            let chunk = |hash: &u128| -> &[u8] {
+                fetched.get(hash).or_else(|| shared.get(hash)).expect("chunk was read")
+            };
+            group.par_iter()
+                .map(|plan| plan.write(output_path, mode, chunk))
+                .collect::<Result<(), StoreError>>()?;
+            
+            for hash in group.iter().flat_map(ExtractPlan::chunk_refs) {
+                let count = remaining.get_mut(&hash).expect("chunk was counted");
+                *count -= 1;
+                if *count == 0 {
+                    shared.remove(&hash);
+                } else if let Some(data) = fetched.remove(&hash) {
+                    shared.insert(hash, data);
                 }
-                _ => {}
             }
         }
         
-        // 复制保留的文件
-        let mods_dir = PathBuf::from(self.store.base_path()).join("mods").join(mod_id);
+        linked.par_iter()
+            .map(|(cached, path)| link_cached(cached, &output_path.join(path), mode))
+            .collect::<Result<(), _>>()?;
+        
+        for dir in &manifest.empty_dirs {
+            fs::create_dir_all(output_path.join(dir))?;
+        }
+        
+        // 复制保留的文件；缺失时提取结果不完整，不能静默跳过
         for relative_path in &manifest.preserved_files {
             let src = mods_dir.join(relative_path);
             let dst = output_path.join(relative_path);
+            if !src.is_file() {
+                return Err(StoreError::PreservedFileNotFound(src.display().to_string()));
+            }
             if let Some(parent) = dst.parent() {
                 fs::create_dir_all(parent)?;
             }
-            if src.exists() {
-                fs::copy(&src, &dst)?;
-            }
+            fs::copy(&src, &dst)?;
         }
         
         Ok(())
@@ -292,51 +1293,181 @@ impl ModArchive {
     
     /// 删除 mod
     pub fn remove_mod(&mut self, mod_id: &str) -> Result<bool, StoreError> {
-        if let Some((_, manifest_json)) = self.store.get_mod(mod_id)? {
-            let manifest: ModManifest = serde_json::from_str(&manifest_json)
-                .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-            
-            // 减少 DDS 块引用
-            let mut hashes = Vec::new();
-            for file in &manifest.files {
-                if file.file_type.as_deref() == Some("dds") {
-                    for hash_str in &file.chunks {
-                        if let Ok(hash) = u128::from_str_radix(hash_str, 16) {
-                            hashes.push(hash);
-                        }
-                    }
-                }
-            }
+        if let Some(manifest) = self.load_manifest(mod_id)? {
+            let versions = self.load_versions(mod_id)?;
             
-            if !hashes.is_empty() {
-                self.store.decrement_chunk_refs(&hashes)?;
+            // 减少分块文件的块引用（含历史版本）
+            let mut hashes = manifest.chunk_refs()?;
+            for (_, old) in &versions {
+                hashes.extend(old.chunk_refs()?);
             }
             
-            // 删除压缩文件
-            for file in &manifest.files {
-                if matches!(file.file_type.as_deref(), Some("buf") | Some("ib")) {
-                    let compressed_path = PathBuf::from(self.store.base_path())
-                        .join("compressed")
-                        .join(format!("{}.zst", &file.chunks[0]));
-                    let _ = fs::remove_file(compressed_path);
+            // 压缩文件和保留文件目录在数据库提交后删除
+            let mut staging = Staging::create(Path::new(self.store.base_path()))?;
+            for m in std::iter::once(&manifest).chain(versions.iter().map(|(_, m)| m)) {
+                for file_id in m.compressed_ids() {
+                    staging.push(FileOp::Remove {
+                        path: Path::new("compressed").join(format!("{}.zst", file_id)),
+                    });
                 }
             }
+            staging.push(FileOp::Remove { path: Path::new("mods").join(mod_id) });
+            staging.push(FileOp::Remove { path: Path::new("versions").join(mod_id) });
             
-            // 删除保留文件目录
-            let mods_dir = PathBuf::from(self.store.base_path()).join("mods").join(mod_id);
-            let _ = fs::remove_dir_all(mods_dir);
-            
-            self.store.delete_mod(mod_id)?;
+            let plan = staging.plan()?;
+            if let Err(e) = self.store.commit_remove(mod_id, &hashes, (staging.token(), &plan)) {
+                staging.discard();
+                return Err(e);
+            }
+            staging.finish_committed(&self.store.conn);
             Ok(true)
         } else {
             Ok(false)
         }
     }
     
+    /// 保留文件目录
+    pub(crate) fn preserved_dir(&self, mod_id: &str) -> PathBuf {
+        PathBuf::from(self.store.base_path()).join("mods").join(mod_id)
+    }
+    
+    /// 提取缓存中某种文件内容的路径（按 hash 算法和校验和前两位分目录）
+    fn extract_cache_path(&self, algorithm: HashAlgorithm, checksum: &str) -> PathBuf {
+        PathBuf::from(self.store.base_path())
+            .join(EXTRACT_CACHE_DIR)
+            .join(algorithm.name())
+            .join(checksum.get(..2).unwrap_or(checksum))
+            .join(checksum)
+    }
+    
+    /// 删除提取缓存，返回删除的文件数和字节数
+    ///
+    /// 已经链接到输出目录的文件不受影响，之后的提取重新还原缓存。
+    pub fn clear_extract_cache(&self) -> Result<(usize, u64), StoreError> {
+        let dir = PathBuf::from(self.store.base_path()).join(EXTRACT_CACHE_DIR);
+        if !dir.exists() {
+            return Ok((0, 0));
+        }
+        let mut removed = (0, 0);
+        for entry in WalkDir::new(&dir) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if entry.file_type().is_file() {
+                removed.0 += 1;
+                removed.1 += entry.metadata().map_err(std::io::Error::from)?.len();
+            }
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(removed)
+    }
+    
+    /// 历史版本的保留文件目录
+    pub(crate) fn version_dir(&self, mod_id: &str, version: i64) -> PathBuf {
+        PathBuf::from(self.store.base_path())
+            .join("versions")
+            .join(mod_id)
+            .join(version.to_string())
+    }
+    
+    /// 读取 mod 的所有历史版本清单
+    pub(crate) fn load_versions(&self, mod_id: &str) -> Result<Vec<(i64, ModManifest)>, StoreError> {
+        let mut versions = Vec::new();
+        for (version, _) in self.store.list_mod_versions(mod_id)? {
+            if let Some(manifest_json) = self.store.get_mod_version(mod_id, version)? {
+                let manifest = self.read_manifest(&format!("{}@{}", mod_id, version), &manifest_json)?;
+                versions.push((version, manifest));
+            }
+        }
+        Ok(versions)
+    }
+    
+    /// 确定单个文件的还原方式
+    pub(crate) fn extract_plan<'a>(&self, manifest: &ModManifest, file: &'a FileManifest) -> Result<ExtractPlan<'a>, StoreError> {
+        let is_legacy_buffer = matches!(file.file_type.as_deref(), Some("buf") | Some("ib"));
+        let source = if is_legacy_buffer && file.is_compressed() {
+            let file_id = file.chunks.first().ok_or_else(|| {
+                StoreError::CorruptManifest(format!("no compressed file id: {}", file.path))
+            })?;
+            ExtractSource::Compressed(self.compressed_path(file_id))
+        } else {
+            let hashes = file.chunks.iter()
+                .map(|h| parse_hash(h))
+                .collect::<Result<_, _>>()?;
+            let dds = match file.file_type.as_deref() {
+                Some("dds") => {
+                    let dds_metadata = file.dds_metadata.as_ref().ok_or_else(|| {
+                        StoreError::CorruptManifest(format!("missing DDS metadata: {}", file.path))
+                    })?;
+                    let header = match &dds_metadata.header_chunk {
+                        Some(header_hash) => DdsHeader::Chunk(parse_hash(header_hash)?),
+                        // 旧版清单没有保存文件头，只能从原始目录读取
+                        None => DdsHeader::Source(PathBuf::from(&manifest.source_path).join(&file.path)),
+                    };
+                    let metadata = DdsMetadata {
+                        header_size: dds_metadata.header_size,
+                        width: dds_metadata.width,
+                        height: dds_metadata.height,
+                        format: dds_metadata.format.clone(),
+                        ..Default::default()
+                    };
+                    Some((metadata, header))
+                }
+                _ => None,
+            };
+            ExtractSource::Chunks { hashes, dds }
+        };
+        
+        Ok(ExtractPlan {
+            path: &file.path,
+            original_size: file.original_size,
+            checksum: file.checksum.as_deref(),
+            source,
+            cache: None,
+        })
+    }
+    
+    /// buf/ib 压缩文件的存放路径
+    pub(crate) fn compressed_path(&self, file_id: &str) -> PathBuf {
+        PathBuf::from(self.store.base_path())
+            .join("compressed")
+            .join(format!("{}.zst", file_id))
+    }
+    
+    /// 读取并解析 mod 清单
+    pub(crate) fn load_manifest(&self, mod_id: &str) -> Result<Option<ModManifest>, StoreError> {
+        match self.store.get_mod(mod_id)? {
+            Some((_, manifest_json)) => self.read_manifest(mod_id, &manifest_json).map(Some),
+            None => Ok(None),
+        }
+    }
+    
+    /// 解析清单，拒绝与存储使用不同 hash 算法的清单
+    pub(crate) fn read_manifest(&self, label: &str, manifest_json: &str) -> Result<ModManifest, StoreError> {
+        let manifest = parse_manifest(label, manifest_json)?;
+        if manifest.hash_algorithm != self.config.hash_algorithm {
+            return Err(StoreError::HashAlgorithm(format!(
+                "manifest {} uses {}, store uses {}",
+                label, manifest.hash_algorithm.name(), self.config.hash_algorithm.name()
+            )));
+        }
+        Ok(manifest)
+    }
+    
     pub fn gc(&mut self) -> Result<(usize, u64), StoreError> {
         self.store.gc()
     }
     
+    pub fn backend_name(&self) -> &str {
+        self.store.backend_name()
+    }
+    
+    pub fn migrate_backend(&mut self, backend: BackendKind) -> Result<usize, StoreError> {
+        self.store.migrate_backend(backend)
+    }
+    
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, StoreError> {
+        self.store.fsck(repair)
+    }
+    
     pub fn get_stats(&self) -> Result<StoreStats, StoreError> {
         self.store.get_stats()
     }
@@ -345,3 +1476,472 @@ impl ModArchive {
         self.store.list_mods()
     }
 }
+
+/// 解析清单 JSON
+pub(crate) fn parse_manifest(mod_id: &str, manifest_json: &str) -> Result<ModManifest, StoreError> {
+    serde_json::from_str(manifest_json)
+        .map_err(|e| StoreError::CorruptManifest(format!("{}: {}", mod_id, e)))
+}
+
+/// 按扩展名（小写）判断文件是否原样保留而不分块
+pub(crate) fn is_preserved(ext: &str) -> bool {
+    matches!(ext, "ini" | "png" | "jpg" | "jpeg" | "webp" | "gif" | "txt" | "md" | "json")
+}
+
+/// 文件修改时间（Unix 纪元以来的纳秒，无法获取时为 0）
+fn modified_nanos(metadata: &fs::Metadata) -> i64 {
+    metadata.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as i64)
+}
+
+/// 解析清单中的块 hash
+pub(crate) fn parse_hash(hex: &str) -> Result<u128, StoreError> {
+    u128::from_str_radix(hex, 16)
+        .map_err(|e| StoreError::CorruptManifest(format!("invalid chunk hash {}: {}", hex, e)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::prepare_chunks_with;
+    use tempfile::tempdir;
+    
+    fn make_dds(width: u32, height: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; 128];
+        data[0..4].copy_from_slice(b"DDS ");
+        data[4..8].copy_from_slice(&124u32.to_le_bytes());
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[84..88].copy_from_slice(b"DXT5");
+        data.extend_from_slice(payload);
+        data
+    }
+    
+    #[test]
+    fn test_extract_without_source() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let payload: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let dds = make_dds(64, 32, &payload);
+        fs::write(src.path().join("a.dds"), &dds).unwrap();
+        fs::write(src.path().join("mod.ini"), b"[TextureOverride]").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        // 归档后删除源目录，解压不应再依赖它
+        drop(src);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("a.dds")).unwrap(), dds);
+        
+        // 存储中的保留文件缺失时报错，而不是输出不完整的 mod
+        fs::remove_file(store.path().join("mods/m/mod.ini")).unwrap();
+        assert!(matches!(
+            archive.extract_mod("m", out.path()),
+            Err(StoreError::PreservedFileNotFound(_))
+        ));
+    }
+    
+    #[test]
+    fn test_mip_aligned_variants_share_lower_mips() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        // 320x320 BC1 的 9 个 mip 层级；另一个纹理只包含它的低 8 层
+        let sizes = [51200usize, 12800, 3200, 800, 200, 72, 32, 8, 8];
+        let payload: Vec<u8> = (0..sizes.iter().sum::<usize>())
+            .map(|i| (i.wrapping_mul(2654435761) >> 7) as u8)
+            .collect();
+        let bc1 = |size: u32, mips: u32, payload: &[u8]| {
+            let mut data = make_dds(size, size, payload);
+            data[28..32].copy_from_slice(&mips.to_le_bytes());
+            data[80..84].copy_from_slice(&0x4u32.to_le_bytes());
+            data[84..88].copy_from_slice(b"DXT1");
+            data
+        };
+        let full = bc1(320, 9, &payload);
+        let lower = bc1(160, 8, &payload[sizes[0]..]);
+        fs::write(src.path().join("full.dds"), &full).unwrap();
+        fs::write(src.path().join("lower.dds"), &lower).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        let file = |name: &str| report.manifest.files.iter().find(|f| f.path == name).unwrap();
+        assert_eq!(file("full.dds").chunking, Some(ChunkingStrategy::MipAligned { size: 4096 }));
+        let full_chunks: HashSet<&String> = file("full.dds").chunks.iter().collect();
+        assert!(file("lower.dds").chunks.iter().all(|c| full_chunks.contains(c)));
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("full.dds")).unwrap(), full);
+        assert_eq!(fs::read(out.path().join("lower.dds")).unwrap(), lower);
+    }
+    
+    #[test]
+    fn test_roundtrip_all_files() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("shaders/a.hlsl", b"float4 main() {}".to_vec()),
+            ("sound.ogg", (0..9000u32).map(|i| (i * 7) as u8).collect()),
+            ("broken.dds", b"not a texture".to_vec()),
+            ("empty.dat", Vec::new()),
+            ("mod.ini", b"[TextureOverride]".to_vec()),
+        ];
+        for (path, data) in &files {
+            let path = src.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        fs::create_dir_all(src.path().join("empty_dir")).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert_eq!(report.count(FileHandling::Generic), 4);
+        assert_eq!(report.count(FileHandling::Preserved), 1);
+        assert_eq!(report.warnings().count(), 1);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        for (path, data) in &files {
+            assert_eq!(&fs::read(out.path().join(path)).unwrap(), data, "{}", path);
+        }
+        assert!(out.path().join("empty_dir").is_dir());
+    }
+    
+    #[test]
+    fn test_mixed_chunking_strategies() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i.wrapping_mul(2654435761) >> 11) as u8).collect();
+        fs::write(src.path().join("a.dat"), &data).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("fixed"), None).unwrap();
+        archive.set_chunking(ChunkingStrategy::content_defined(4096)).unwrap();
+        let report = archive.archive_mod(src.path(), Some("cdc"), None).unwrap();
+        assert_eq!(report.manifest.files[0].chunking, Some(ChunkingStrategy::content_defined(4096)));
+        
+        for id in ["fixed", "cdc"] {
+            archive.extract_mod(id, &out.path().join(id)).unwrap();
+            assert_eq!(fs::read(out.path().join(id).join("a.dat")).unwrap(), data);
+        }
+        assert!(archive.verify_all().unwrap().iter().all(|r| r.is_ok()));
+    }
+    
+    #[test]
+    fn test_rearchive_existing_id() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let first: Vec<u8> = (0..9000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(src.path().join("a.dat"), &first).unwrap();
+        fs::write(src.path().join("mod.ini"), b"v1").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        // 替换：旧引用被释放，不产生引用漂移
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        let reject = ArchiveOptions {
+            id: Some("m".to_string()),
+            on_existing: OnExisting::Reject,
+            ..Default::default()
+        };
+        assert!(matches!(
+            archive.archive_mod_with(src.path(), &reject),
+            Err(StoreError::ModExists(_))
+        ));
+        
+        // 保留为历史版本
+        fs::write(src.path().join("a.dat"), b"changed").unwrap();
+        fs::write(src.path().join("mod.ini"), b"v2").unwrap();
+        let version = ArchiveOptions {
+            id: Some("m".to_string()),
+            on_existing: OnExisting::Version,
+            ..Default::default()
+        };
+        let report = archive.archive_mod_with(src.path(), &version).unwrap();
+        assert_eq!(report.version, Some(1));
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        archive.extract_version("m", 1, out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("a.dat")).unwrap(), first);
+        assert_eq!(fs::read(out.path().join("mod.ini")).unwrap(), b"v1");
+        
+        // 删除 mod 同时释放历史版本的引用
+        archive.remove_mod("m").unwrap();
+        let (deleted, _) = archive.gc().unwrap();
+        assert_eq!(deleted, 4);
+        assert!(archive.fsck(false).unwrap().is_clean());
+    }
+    
+    #[test]
+    fn test_incremental_rearchive() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let big: Vec<u8> = (0..40_000u32).map(|i| (i.wrapping_mul(2654435761) >> 7) as u8).collect();
+        fs::write(src.path().join("big.dat"), &big).unwrap();
+        fs::write(src.path().join("other.dat"), b"first").unwrap();
+        fs::write(src.path().join("gone.dat"), b"removed later").unwrap();
+        fs::write(src.path().join("mod.ini"), b"v1").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert_eq!(report.changes.added.len(), 4);
+        assert_eq!(report.changes.reused, 0);
+        
+        // 内容改变但大小和修改时间不变的文件被视为未改动，证明它没有被重新读取
+        let mtime = fs::metadata(src.path().join("big.dat")).unwrap().modified().unwrap();
+        fs::write(src.path().join("big.dat"), vec![0u8; big.len()]).unwrap();
+        fs::File::options().write(true).open(src.path().join("big.dat")).unwrap().set_modified(mtime).unwrap();
+        
+        fs::write(src.path().join("other.dat"), b"second version").unwrap();
+        fs::write(src.path().join("mod.ini"), b"v2").unwrap();
+        fs::remove_file(src.path().join("gone.dat")).unwrap();
+        fs::write(src.path().join("new.dat"), b"added").unwrap();
+        
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        let mut modified = report.changes.modified.clone();
+        modified.sort();
+        assert_eq!(modified, ["mod.ini", "other.dat"]);
+        assert_eq!(report.changes.added, ["new.dat"]);
+        assert_eq!(report.changes.removed, ["gone.dat"]);
+        assert_eq!(report.changes.unchanged, 1);
+        assert_eq!(report.changes.reused, 1);
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("big.dat")).unwrap(), big);
+        assert_eq!(fs::read(out.path().join("other.dat")).unwrap(), b"second version");
+        assert_eq!(fs::read(out.path().join("mod.ini")).unwrap(), b"v2");
+        
+        // 没有任何变化时不写入新块
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert!(report.changes.is_empty());
+        assert_eq!(report.manifest.stored_size, 0);
+    }
+    
+    #[test]
+    fn test_typed_errors() {
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let archive = ModArchive::open(store.path()).unwrap();
+        
+        assert!(matches!(
+            archive.extract_mod("missing", out.path()),
+            Err(StoreError::ModNotFound(_))
+        ));
+        
+        archive.store.save_mod("broken", "broken", "{not json").unwrap();
+        assert!(matches!(
+            archive.extract_mod("broken", out.path()),
+            Err(StoreError::CorruptManifest(_))
+        ));
+    }
+    
+    #[test]
+    fn test_bounded_batches_and_interrupted_archive() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let files: Vec<Vec<u8>> = (0..20u32)
+            .map(|n| (0..10_000u32).map(|i| (i.wrapping_mul(2654435761 + n) >> 13) as u8).collect())
+            .collect();
+        for (n, data) in files.iter().enumerate() {
+            fs::write(src.path().join(format!("{:02}.dat", n)), data).unwrap();
+        }
+        
+        // 上限远小于 mod 大小，每个文件单独成批
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.set_memory_limit(16 * 1024);
+        let report = archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert_eq!(report.count(FileHandling::Generic), 20);
+        assert!(report.manifest.stored_size > 0);
+        
+        let pending: i64 = archive.store.conn
+            .query_row("SELECT COUNT(*) FROM pending_refs", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(pending, 0);
+        archive.extract_mod("m", out.path()).unwrap();
+        for (n, data) in files.iter().enumerate() {
+            assert_eq!(&fs::read(out.path().join(format!("{:02}.dat", n))).unwrap(), data);
+        }
+        
+        // 清单提交前中断：重新打开时撤销已写入批次的引用
+        let data = vec![7u8; 8192];
+        let chunks = prepare_chunks_with(vec![(&data[..4096], None), (&data[4096..], None)], 3).unwrap();
+        archive.store.put_pending("interrupted", &chunks).unwrap();
+        drop(archive);
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        assert!(archive.fsck(false).unwrap().is_clean());
+        assert_eq!(archive.gc().unwrap().0, 1);
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+    }
+    
+    #[test]
+    fn test_extract_repeated_chunks() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        // 同一块在文件内和多个分组之间重复出现
+        let blank = vec![0u8; 64 * 1024];
+        let noise: Vec<u8> = (0..40_000u32).map(|i| (i.wrapping_mul(2654435761) >> 11) as u8).collect();
+        let files: Vec<Vec<u8>> = (0..6)
+            .map(|n| if n % 2 == 0 { [&blank[..], &noise[..], &blank[..]].concat() } else { blank.clone() })
+            .collect();
+        for (n, data) in files.iter().enumerate() {
+            fs::write(src.path().join(format!("{}.dat", n)), data).unwrap();
+        }
+        
+        let mut archive = ModArchive::create(store.path(), BackendKind::Pack).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        let manifest = archive.load_manifest("m").unwrap().unwrap();
+        let refs = manifest.chunk_refs().unwrap();
+        let unique: HashSet<u128> = refs.iter().copied().collect();
+        assert!(unique.len() < refs.len());
+        
+        let map = archive.store.read_chunk_map(&refs).unwrap();
+        assert_eq!(map.len(), unique.len());
+        assert_eq!(archive.store.read_chunks(&refs).unwrap().concat().len() as u64, manifest.original_size);
+        
+        archive.set_memory_limit(200 * 1024);
+        archive.extract_mod("m", out.path()).unwrap();
+        for (n, data) in files.iter().enumerate() {
+            assert_eq!(&fs::read(out.path().join(format!("{}.dat", n))).unwrap(), data);
+        }
+    }
+    
+    #[test]
+    fn test_archive_many() {
+        let mods = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let shared: Vec<u8> = (0..30_000u32).map(|i| (i.wrapping_mul(2654435761) >> 9) as u8).collect();
+        for name in ["a", "b", "c"] {
+            let dir = mods.path().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("shared.dat"), &shared).unwrap();
+            fs::write(dir.join("own.dat"), name.repeat(5000)).unwrap();
+            fs::write(dir.join("mod.ini"), name).unwrap();
+        }
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(&mods.path().join("c"), None, None).unwrap();
+        
+        let reject = ArchiveOptions { on_existing: OnExisting::Reject, ..Default::default() };
+        let jobs: Vec<(PathBuf, ArchiveOptions)> = ["a", "b", "c"].iter()
+            .map(|name| (mods.path().join(name), reject.clone()))
+            .collect();
+        let results = archive.archive_many(&jobs, 2).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().manifest.id, "a");
+        assert_eq!(results[1].as_ref().unwrap().manifest.id, "b");
+        assert!(matches!(results[2], Err(StoreError::ModExists(_))));
+        
+        for name in ["a", "b"] {
+            archive.extract_mod(name, &out.path().join(name)).unwrap();
+            assert_eq!(fs::read(out.path().join(name).join("shared.dat")).unwrap(), shared);
+            assert_eq!(fs::read(out.path().join(name).join("mod.ini")).unwrap(), name.as_bytes());
+        }
+        
+        // 共享的块被三个 mod 引用，删除一个后仍然可用
+        archive.remove_mod("a").unwrap();
+        archive.gc().unwrap();
+        assert!(archive.verify_all().unwrap().iter().all(|r| r.is_ok()));
+        assert!(archive.fsck(false).unwrap().is_clean());
+    }
+    
+    #[test]
+    fn test_extract_cache_links() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let shared = make_dds(64, 64, &[9u8; 64 * 64]);
+        fs::write(first.path().join("shared.dds"), &shared).unwrap();
+        fs::write(first.path().join("copy.dds"), &shared).unwrap();
+        fs::write(second.path().join("shared.dds"), &shared).unwrap();
+        fs::write(second.path().join("own.dat"), b"second").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(first.path(), Some("a"), None).unwrap();
+        archive.archive_mod(second.path(), Some("b"), None).unwrap();
+        archive.set_extract_mode(ExtractMode::Hardlink);
+        for id in ["a", "b"] {
+            archive.extract_mod(id, &out.path().join(id)).unwrap();
+        }
+        // 再次提取到同一目录时替换已有的链接
+        archive.extract_mod("a", &out.path().join("a")).unwrap();
+        for path in ["a/shared.dds", "a/copy.dds", "b/shared.dds"] {
+            assert_eq!(fs::read(out.path().join(path)).unwrap(), shared);
+        }
+        assert_eq!(fs::read(out.path().join("b/own.dat")).unwrap(), b"second");
+        
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: &str| fs::metadata(out.path().join(path)).unwrap().ino();
+            assert_eq!(inode("a/shared.dds"), inode("b/shared.dds"));
+            assert_eq!(inode("a/copy.dds"), inode("b/shared.dds"));
+            
+            // 复制模式不改写链接指向的缓存
+            archive.set_extract_mode(ExtractMode::Copy);
+            archive.extract_mod("b", &out.path().join("b")).unwrap();
+            assert_ne!(inode("a/shared.dds"), inode("b/shared.dds"));
+        }
+        
+        assert_eq!(archive.clear_extract_cache().unwrap(), (2, shared.len() as u64 + 6));
+        assert_eq!(fs::read(out.path().join("a/shared.dds")).unwrap(), shared);
+        
+        archive.set_extract_mode(ExtractMode::Reflink);
+        archive.extract_mod("b", &out.path().join("reflink")).unwrap();
+        assert_eq!(fs::read(out.path().join("reflink/shared.dds")).unwrap(), shared);
+        assert!(!fs::metadata(out.path().join("reflink/shared.dds")).unwrap().permissions().readonly());
+    }
+    
+    #[test]
+    fn test_extract_cache_survives_edit() {
+        use std::io::Write;
+        
+        let source = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(source.path().join("a.dat"), &data).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(source.path(), Some("a"), None).unwrap();
+        archive.set_extract_mode(ExtractMode::Hardlink);
+        archive.extract_mod("a", &out.path().join("first")).unwrap();
+        
+        // 缓存文件只读；去掉只读属性后原地写入相同大小的内容，会改动共享的缓存
+        let edited = out.path().join("first/a.dat");
+        assert!(fs::metadata(&edited).unwrap().permissions().readonly());
+        make_writable(&edited).unwrap();
+        fs::OpenOptions::new().write(true).open(&edited).unwrap().write_all(&[0u8; 100]).unwrap();
+        assert_eq!(fs::metadata(&edited).unwrap().len(), data.len() as u64);
+        
+        archive.extract_mod("a", &out.path().join("second")).unwrap();
+        assert_eq!(fs::read(out.path().join("second/a.dat")).unwrap(), data);
+        assert!(fs::metadata(out.path().join("second/a.dat")).unwrap().permissions().readonly());
+    }
+}
@@ -0,0 +1,834 @@
+//! 块后端
+//!
+//! `ChunkBackend` 负责块数据与引用计数的保存，清单、元数据和日志始终在 `store.db` 中。
+//!
+//! - `SqliteBackend`：块在 `chunks` 表中（BLOB 或包文件索引），与清单在同一事务中提交
+//! - `MemoryBackend`：块保存在内存中，用于单元测试
+//! - `DirectoryBackend`：每个块一个文件，按 hash 前缀分目录
+//!
+//! 非 SQLite 后端的写入不参与清单事务：块和新增的引用在清单提交前写入，
+//! 释放的引用在提交成功后才减少，中途失败只会留下多余的引用计数，由 `fsck` 修复。
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::chunk::{Codec, PreparedChunk};
+use crate::pack::{self, PackStore, PackWriter};
+use crate::schema;
+use crate::store::{BackendKind, RawChunk, StoreError};
+
+/// `convert` 每批移动的块数
+const CONVERT_BATCH: usize = 1024;
+
+/// `get_many` 每条查询的 hash 数
+const QUERY_BATCH: usize = 500;
+
+/// 块的索引信息
+#[derive(Debug, Clone)]
+pub struct ChunkEntry {
+    pub hash: u128,
+    pub original_size: usize,
+    /// 压缩后占用的字节数
+    pub stored_size: u64,
+    pub ref_count: i64,
+    pub codec: Codec,
+}
+
+/// 块数据的存储方式
+pub trait ChunkBackend {
+    /// 后端名称（显示用）
+    fn name(&self) -> &str;
+    
+    /// 写入是否随 `ChunkStore` 已开启的事务一起提交或回滚
+    fn transactional(&self) -> bool {
+        false
+    }
+    
+    /// 写入块：已存在的块增加引用计数，返回新写入的压缩字节数
+    fn put(&mut self, chunks: &[PreparedChunk]) -> Result<u64, StoreError>;
+    
+    /// 读取块的压缩数据
+    fn get(&self, hash: u128) -> Result<Option<RawChunk>, StoreError>;
+    
+    /// 批量读取块，不存在的块不在结果中
+    fn get_many(&self, hashes: &[u128]) -> Result<HashMap<u128, RawChunk>, StoreError> {
+        let mut chunks = HashMap::with_capacity(hashes.len());
+        for &hash in hashes {
+            if let Some(chunk) = self.get(hash)? {
+                chunks.insert(hash, chunk);
+            }
+        }
+        Ok(chunks)
+    }
+    
+    /// 增加引用计数（块不存在时忽略）
+    fn add_ref(&mut self, hashes: &[u128]) -> Result<(), StoreError>;
+    
+    /// 减少引用计数，计数为 0 的块保留到 `sweep`
+    fn unref(&mut self, hashes: &[u128]) -> Result<(), StoreError>;
+    
+    /// 设置引用计数（`fsck` 修复）
+    fn set_ref(&mut self, hash: u128, ref_count: i64) -> Result<(), StoreError>;
+    
+    /// 替换块的编码数据与编码方式（重新编码），引用计数不变
+    fn replace(&mut self, updates: &[(u128, Vec<u8>, Codec)]) -> Result<(), StoreError>;
+    
+    /// 删除块（不论引用计数）
+    fn remove(&mut self, hashes: &[u128]) -> Result<(), StoreError>;
+    
+    /// 删除引用计数为 0 的块并回收空间，返回 (删除的块数, 释放的字节数)
+    fn sweep(&mut self) -> Result<(usize, u64), StoreError>;
+    
+    /// 遍历所有块
+    fn for_each(&self, f: &mut dyn FnMut(&ChunkEntry) -> Result<(), StoreError>) -> Result<(), StoreError>;
+    
+    /// 已删除的块仍占用、等待 `sweep` 回收的字节数
+    fn garbage_size(&self) -> Result<u64, StoreError> {
+        Ok(0)
+    }
+    
+    /// 在同一后端的不同布局之间转换，返回移动的块数
+    fn convert(&mut self, kind: BackendKind) -> Result<usize, StoreError> {
+        Err(StoreError::InvalidConfig(format!(
+            "{} backend cannot be converted to {}", self.name(), kind.as_str()
+        )))
+    }
+}
+
+/// 块保存在 `chunks` 表中：`data` BLOB，或 pack 布局下的包文件索引
+///
+/// 与 `ChunkStore` 共用连接，在已开启的事务中调用时随该事务一起提交。
+pub struct SqliteBackend {
+    conn: Rc<Connection>,
+    packs: PackStore,
+    kind: BackendKind,
+}
+
+impl SqliteBackend {
+    pub(crate) fn new(conn: Rc<Connection>, base: &Path, kind: BackendKind) -> Self {
+        Self {
+            conn,
+            packs: PackStore::new(base),
+            kind,
+        }
+    }
+    
+    /// 在当前事务中执行，没有事务时开启一个
+    fn in_tx<T>(&self, f: impl FnOnce(&Connection, &PackStore) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        if !self.conn.is_autocommit() {
+            return f(&self.conn, &self.packs);
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        let result = f(&tx, &self.packs)?;
+        tx.commit()?;
+        Ok(result)
+    }
+    
+    fn writer<'a>(packs: &PackStore, kind: BackendKind, conn: &'a Connection) -> Option<PackWriter<'a>> {
+        (kind == BackendKind::Pack).then(|| packs.writer(conn))
+    }
+}
+
+impl ChunkBackend for SqliteBackend {
+    fn name(&self) -> &str {
+        self.kind.as_str()
+    }
+    
+    fn transactional(&self) -> bool {
+        true
+    }
+    
+    fn put(&mut self, chunks: &[PreparedChunk]) -> Result<u64, StoreError> {
+        let kind = self.kind;
+        self.in_tx(|conn, packs| {
+            let mut writer = Self::writer(packs, kind, conn);
+            let stored_size = insert_chunks(conn, writer.as_mut(), chunks)?;
+            if let Some(writer) = writer {
+                writer.finish()?;
+            }
+            Ok(stored_size)
+        })
+    }
+    
+    fn get(&self, hash: u128) -> Result<Option<RawChunk>, StoreError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT data, original_size, codec, dict_id, pack_id, pack_offset, pack_len FROM chunks WHERE hash = ?"
+        )?;
+        let hash_bytes = hash.to_le_bytes();
+        let row = stmt.query_row([&hash_bytes[..]], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ))
+        }).optional()?;
+        
+        let Some((data, original_size, codec, dict_id, pack_id, offset, len)) = row else {
+            return Ok(None);
+        };
+        let data = match (pack_id, offset, len) {
+            (Some(pack_id), Some(offset), Some(len)) => self.packs.read(pack_id, offset as u64, len as usize)?,
+            _ => data,
+        };
+        Ok(Some((data, original_size as usize, Codec::from_columns(codec, dict_id)?)))
+    }
+    
+    /// 每条查询取一批块；包中的块按包和偏移顺序读取
+    fn get_many(&self, hashes: &[u128]) -> Result<HashMap<u128, RawChunk>, StoreError> {
+        let mut chunks = HashMap::with_capacity(hashes.len());
+        let mut located: Vec<(i64, u64, usize, u128)> = Vec::new();
+        
+        for batch in hashes.chunks(QUERY_BATCH) {
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT hash, data, original_size, codec, dict_id, pack_id, pack_offset, pack_len
+                 FROM chunks WHERE hash IN ({})",
+                vec!["?"; batch.len()].join(", ")
+            ))?;
+            let keys: Vec<[u8; 16]> = batch.iter().map(|h| h.to_le_bytes()).collect();
+            let mut rows = stmt.query(rusqlite::params_from_iter(keys.iter().map(|k| &k[..])))?;
+            while let Some(row) = rows.next()? {
+                let hash_bytes: Vec<u8> = row.get(0)?;
+                let Ok(bytes) = <[u8; 16]>::try_from(hash_bytes.as_slice()) else {
+                    continue;
+                };
+                let hash = u128::from_le_bytes(bytes);
+                let codec = Codec::from_columns(row.get(3)?, row.get(4)?)?;
+                let location: (Option<i64>, Option<i64>, Option<i64>) = (row.get(5)?, row.get(6)?, row.get(7)?);
+                if let (Some(pack_id), Some(offset), Some(len)) = location {
+                    located.push((pack_id, offset as u64, len as usize, hash));
+                }
+                chunks.insert(hash, (row.get(1)?, row.get::<_, i64>(2)? as usize, codec));
+            }
+        }
+        
+        located.sort_unstable();
+        for (pack_id, offset, len, hash) in located {
+            let data = self.packs.read(pack_id, offset, len)?;
+            if let Some(chunk) = chunks.get_mut(&hash) {
+                chunk.0 = data;
+            }
+        }
+        Ok(chunks)
+    }
+    
+    fn add_ref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        self.in_tx(|conn, _| {
+            let mut stmt = conn.prepare_cached("UPDATE chunks SET ref_count = ref_count + 1 WHERE hash = ?")?;
+            for hash in hashes {
+                stmt.execute([&hash.to_le_bytes()[..]])?;
+            }
+            Ok(())
+        })
+    }
+    
+    fn unref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        self.in_tx(|conn, _| release_chunks(conn, hashes))
+    }
+    
+    fn set_ref(&mut self, hash: u128, ref_count: i64) -> Result<(), StoreError> {
+        self.conn.prepare_cached("UPDATE chunks SET ref_count = ? WHERE hash = ?")?
+            .execute(params![ref_count, &hash.to_le_bytes()[..]])?;
+        Ok(())
+    }
+    
+    fn replace(&mut self, updates: &[(u128, Vec<u8>, Codec)]) -> Result<(), StoreError> {
+        let kind = self.kind;
+        self.in_tx(|conn, packs| {
+            let mut writer = Self::writer(packs, kind, conn);
+            for (hash, data, codec) in updates {
+                let hash_bytes = hash.to_le_bytes();
+                match writer.as_mut() {
+                    Some(writer) => {
+                        let (pack_id, offset) = writer.append(data)?;
+                        conn.prepare_cached(
+                            "UPDATE chunks SET data = x'', codec = ?, dict_id = ?, pack_id = ?, pack_offset = ?, pack_len = ?
+                             WHERE hash = ?"
+                        )?.execute(params![
+                            codec.tag(), codec.dict_id(), pack_id, offset as i64, data.len() as i64, &hash_bytes[..]
+                        ])?;
+                    }
+                    None => {
+                        conn.prepare_cached(
+                            "UPDATE chunks SET data = ?, codec = ?, dict_id = ?, pack_id = NULL, pack_offset = NULL, pack_len = NULL
+                             WHERE hash = ?"
+                        )?.execute(params![data, codec.tag(), codec.dict_id(), &hash_bytes[..]])?;
+                    }
+                }
+            }
+            if let Some(writer) = writer {
+                writer.finish()?;
+            }
+            Ok(())
+        })
+    }
+    
+    fn remove(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        self.in_tx(|conn, _| {
+            let mut stmt = conn.prepare_cached("DELETE FROM chunks WHERE hash = ?")?;
+            for hash in hashes {
+                stmt.execute([&hash.to_le_bytes()[..]])?;
+            }
+            Ok(())
+        })
+    }
+    
+    fn sweep(&mut self) -> Result<(usize, u64), StoreError> {
+        let (count, size): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(LENGTH(data)), 0) FROM chunks WHERE ref_count <= 0",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+        self.conn.execute("DELETE FROM chunks WHERE ref_count <= 0", [])?;
+        let reclaimed = pack::compact(&self.conn, &self.packs)?;
+        
+        Ok((count as usize, size as u64 + reclaimed))
+    }
+    
+    fn for_each(&self, f: &mut dyn FnMut(&ChunkEntry) -> Result<(), StoreError>) -> Result<(), StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT hash, original_size, COALESCE(pack_len, LENGTH(data)), ref_count, codec, dict_id FROM chunks"
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let hash_bytes: Vec<u8> = row.get(0)?;
+            let Ok(bytes) = <[u8; 16]>::try_from(hash_bytes.as_slice()) else {
+                continue;
+            };
+            f(&ChunkEntry {
+                hash: u128::from_le_bytes(bytes),
+                original_size: row.get::<_, i64>(1)? as usize,
+                stored_size: row.get::<_, i64>(2)? as u64,
+                ref_count: row.get(3)?,
+                codec: Codec::from_columns(row.get(4)?, row.get(5)?)?,
+            })?;
+        }
+        Ok(())
+    }
+    
+    fn garbage_size(&self) -> Result<u64, StoreError> {
+        let size: i64 = self.conn.query_row(
+            "SELECT (SELECT COALESCE(SUM(size), 0) FROM packs)
+                  - (SELECT COALESCE(SUM(pack_len), 0) FROM chunks WHERE pack_id IS NOT NULL)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(size.max(0) as u64)
+    }
+    
+    /// sqlite 与 pack 布局互转；每批块在独立事务中移动，中断后再次执行会继续
+    fn convert(&mut self, kind: BackendKind) -> Result<usize, StoreError> {
+        let condition = match kind {
+            BackendKind::Sqlite => "pack_id IS NOT NULL",
+            BackendKind::Pack => "pack_id IS NULL",
+            other => return Err(StoreError::InvalidConfig(format!(
+                "{} backend cannot be converted to {}", self.kind.as_str(), other.as_str()
+            ))),
+        };
+        schema::set_meta(&self.conn, "chunk_backend", kind.as_str())?;
+        self.kind = kind;
+        
+        let mut moved = 0;
+        loop {
+            let hashes: Vec<u128> = {
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT hash FROM chunks WHERE {} ORDER BY pack_id, pack_offset LIMIT {}",
+                    condition, CONVERT_BATCH
+                ))?;
+                let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+                let mut hashes = Vec::new();
+                for bytes in rows {
+                    if let Ok(bytes) = <[u8; 16]>::try_from(bytes?.as_slice()) {
+                        hashes.push(u128::from_le_bytes(bytes));
+                    }
+                }
+                hashes
+            };
+            if hashes.is_empty() {
+                break;
+            }
+            
+            let mut updates = Vec::with_capacity(hashes.len());
+            for hash in hashes {
+                if let Some((data, _, codec)) = self.get(hash)? {
+                    updates.push((hash, data, codec));
+                }
+            }
+            moved += updates.len();
+            self.replace(&updates)?;
+        }
+        
+        // 迁回 SQLite 后所有包都没有存活数据，由压缩删除
+        pack::compact(&self.conn, &self.packs)?;
+        Ok(moved)
+    }
+
+}
+
+/// 写入块：已存在的块增加引用计数，返回新写入的压缩字节数
+///
+/// `packs` 为 `None` 时块数据写入 `chunks.data`。
+pub(crate) fn insert_chunks(
+    conn: &Connection,
+    mut packs: Option<&mut PackWriter>,
+    chunks: &[PreparedChunk],
+) -> Result<u64, StoreError> {
+    let mut stored_size = 0u64;
+    
+    let mut stmt_get = conn.prepare_cached(
+        "SELECT ref_count FROM chunks WHERE hash = ?"
+    )?;
+    // zstd 块不写 dict_id 和 codec 列，v4 之前的迁移也可以调用
+    let mut stmt_insert = conn.prepare_cached(
+        "INSERT INTO chunks (hash, data, original_size, ref_count) VALUES (?, ?, ?, 1)"
+    )?;
+    let mut stmt_update = conn.prepare_cached(
+        "UPDATE chunks SET ref_count = ref_count + 1 WHERE hash = ?"
+    )?;
+    
+    for chunk in chunks {
+        let hash_bytes = chunk.hash.to_le_bytes();
+        
+        let exists: Result<i32, _> = stmt_get.query_row([&hash_bytes[..]], |row| row.get(0));
+        
+        if exists.is_ok() {
+            stmt_update.execute([&hash_bytes[..]])?;
+        } else if let Some(writer) = packs.as_mut() {
+            let (pack_id, offset) = writer.append(&chunk.compressed)?;
+            conn.prepare_cached(
+                "INSERT INTO chunks (hash, data, original_size, ref_count, codec, dict_id, pack_id, pack_offset, pack_len)
+                 VALUES (?, x'', ?, 1, ?, ?, ?, ?, ?)"
+            )?.execute(params![
+                &hash_bytes[..],
+                chunk.original_size as i64,
+                chunk.codec.tag(),
+                chunk.codec.dict_id(),
+                pack_id,
+                offset as i64,
+                chunk.compressed.len() as i64
+            ])?;
+            stored_size += chunk.compressed.len() as u64;
+        } else {
+            match chunk.codec {
+                Codec::Zstd => stmt_insert.execute(params![
+                    &hash_bytes[..],
+                    &chunk.compressed,
+                    chunk.original_size as i64
+                ])?,
+                codec => conn.prepare_cached(
+                    "INSERT INTO chunks (hash, data, original_size, ref_count, codec, dict_id) VALUES (?, ?, ?, 1, ?, ?)"
+                )?.execute(params![
+                    &hash_bytes[..],
+                    &chunk.compressed,
+                    chunk.original_size as i64,
+                    codec.tag(),
+                    codec.dict_id()
+                ])?,
+            };
+            stored_size += chunk.compressed.len() as u64;
+        }
+    }
+    
+    Ok(stored_size)
+}
+
+/// 减少块引用计数
+fn release_chunks(conn: &Connection, hashes: &[u128]) -> Result<(), StoreError> {
+    let mut stmt = conn.prepare_cached(
+        "UPDATE chunks SET ref_count = ref_count - 1 WHERE hash = ?"
+    )?;
+    
+    for hash in hashes {
+        let hash_bytes = hash.to_le_bytes();
+        stmt.execute([&hash_bytes[..]])?;
+    }
+    
+    Ok(())
+}
+
+/// 内存中的块
+struct MemoryChunk {
+    data: Vec<u8>,
+    original_size: usize,
+    codec: Codec,
+    ref_count: i64,
+}
+
+/// 块保存在内存中，存储关闭后丢失
+#[derive(Default)]
+pub struct MemoryBackend {
+    chunks: HashMap<u128, MemoryChunk>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChunkBackend for MemoryBackend {
+    fn name(&self) -> &str {
+        "memory"
+    }
+    
+    fn put(&mut self, chunks: &[PreparedChunk]) -> Result<u64, StoreError> {
+        let mut stored_size = 0;
+        for chunk in chunks {
+            match self.chunks.get_mut(&chunk.hash) {
+                Some(existing) => existing.ref_count += 1,
+                None => {
+                    self.chunks.insert(chunk.hash, MemoryChunk {
+                        data: chunk.compressed.clone(),
+                        original_size: chunk.original_size,
+                        codec: chunk.codec,
+                        ref_count: 1,
+                    });
+                    stored_size += chunk.compressed.len() as u64;
+                }
+            }
+        }
+        Ok(stored_size)
+    }
+    
+    fn get(&self, hash: u128) -> Result<Option<RawChunk>, StoreError> {
+        Ok(self.chunks.get(&hash).map(|c| (c.data.clone(), c.original_size, c.codec)))
+    }
+    
+    fn add_ref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for hash in hashes {
+            if let Some(chunk) = self.chunks.get_mut(hash) {
+                chunk.ref_count += 1;
+            }
+        }
+        Ok(())
+    }
+    
+    fn unref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for hash in hashes {
+            if let Some(chunk) = self.chunks.get_mut(hash) {
+                chunk.ref_count -= 1;
+            }
+        }
+        Ok(())
+    }
+    
+    fn set_ref(&mut self, hash: u128, ref_count: i64) -> Result<(), StoreError> {
+        if let Some(chunk) = self.chunks.get_mut(&hash) {
+            chunk.ref_count = ref_count;
+        }
+        Ok(())
+    }
+    
+    fn replace(&mut self, updates: &[(u128, Vec<u8>, Codec)]) -> Result<(), StoreError> {
+        for (hash, data, codec) in updates {
+            if let Some(chunk) = self.chunks.get_mut(hash) {
+                chunk.data = data.clone();
+                chunk.codec = *codec;
+            }
+        }
+        Ok(())
+    }
+    
+    fn remove(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for hash in hashes {
+            self.chunks.remove(hash);
+        }
+        Ok(())
+    }
+    
+    fn sweep(&mut self) -> Result<(usize, u64), StoreError> {
+        let before = (self.chunks.len(), self.chunks.values().map(|c| c.data.len() as u64).sum::<u64>());
+        self.chunks.retain(|_, c| c.ref_count > 0);
+        let after: u64 = self.chunks.values().map(|c| c.data.len() as u64).sum();
+        Ok((before.0 - self.chunks.len(), before.1 - after))
+    }
+    
+    fn for_each(&self, f: &mut dyn FnMut(&ChunkEntry) -> Result<(), StoreError>) -> Result<(), StoreError> {
+        for (&hash, chunk) in &self.chunks {
+            f(&ChunkEntry {
+                hash,
+                original_size: chunk.original_size,
+                stored_size: chunk.data.len() as u64,
+                ref_count: chunk.ref_count,
+                codec: chunk.codec,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// 块文件头：引用计数、原始大小、编码，均为小端 i64
+///
+/// 编码字段为正数时是 zstd 字典 id，0 为 zstd，-1 为未压缩，-2 为 lz4。
+const HEADER_SIZE: usize = 24;
+
+fn codec_to_header(codec: Codec) -> i64 {
+    match codec {
+        Codec::ZstdDict(id) => id,
+        Codec::Zstd => 0,
+        Codec::Raw => -1,
+        Codec::Lz4 => -2,
+    }
+}
+
+fn codec_from_header(value: i64) -> Result<Codec, StoreError> {
+    match value {
+        id if id > 0 => Ok(Codec::ZstdDict(id)),
+        0 => Ok(Codec::Zstd),
+        -1 => Ok(Codec::Raw),
+        -2 => Ok(Codec::Lz4),
+        other => Err(StoreError::Compression(format!("unknown codec in chunk header: {}", other))),
+    }
+}
+
+/// 每个块一个文件：`<root>/<hash 前 2 位>/<hash>`
+pub struct DirectoryBackend {
+    root: PathBuf,
+}
+
+impl DirectoryBackend {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, StoreError> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self { root: root.as_ref().to_path_buf() })
+    }
+    
+    fn path(&self, hash: u128) -> PathBuf {
+        let name = format!("{:032x}", hash);
+        self.root.join(&name[..2]).join(name)
+    }
+    
+    fn read_header(file: &mut File) -> Result<(i64, usize, Codec), StoreError> {
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        let field = |i: usize| i64::from_le_bytes(header[i * 8..i * 8 + 8].try_into().expect("8 bytes"));
+        Ok((field(0), field(1) as usize, codec_from_header(field(2))?))
+    }
+    
+    /// 写入新块（先写临时文件再改名）
+    fn write(&self, hash: u128, ref_count: i64, original_size: usize, codec: Codec, data: &[u8]) -> io::Result<()> {
+        let path = self.path(hash);
+        fs::create_dir_all(path.parent().expect("chunk path has a parent"))?;
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&ref_count.to_le_bytes())?;
+        file.write_all(&(original_size as i64).to_le_bytes())?;
+        file.write_all(&codec_to_header(codec).to_le_bytes())?;
+        file.write_all(data)?;
+        file.sync_data()?;
+        drop(file);
+        fs::rename(tmp, path)
+    }
+    
+    /// 修改引用计数，块不存在时返回 false
+    fn update_ref(&self, hash: u128, f: impl FnOnce(i64) -> i64) -> Result<bool, StoreError> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(self.path(hash)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let (ref_count, _, _) = Self::read_header(&mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&f(ref_count).to_le_bytes())?;
+        Ok(true)
+    }
+    
+    /// 所有块文件 (hash, 路径)
+    fn files(&self) -> Result<Vec<(u128, PathBuf)>, StoreError> {
+        let mut files = Vec::new();
+        for shard in fs::read_dir(&self.root)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(shard.path())? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if let Ok(hash) = u128::from_str_radix(&name.to_string_lossy(), 16) {
+                    files.push((hash, entry.path()));
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl ChunkBackend for DirectoryBackend {
+    fn name(&self) -> &str {
+        BackendKind::Directory.as_str()
+    }
+    
+    fn put(&mut self, chunks: &[PreparedChunk]) -> Result<u64, StoreError> {
+        let mut stored_size = 0;
+        for chunk in chunks {
+            if !self.update_ref(chunk.hash, |count| count + 1)? {
+                self.write(chunk.hash, 1, chunk.original_size, chunk.codec, &chunk.compressed)?;
+                stored_size += chunk.compressed.len() as u64;
+            }
+        }
+        Ok(stored_size)
+    }
+    
+    fn get(&self, hash: u128) -> Result<Option<RawChunk>, StoreError> {
+        let mut file = match File::open(self.path(hash)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let (_, original_size, codec) = Self::read_header(&mut file)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Some((data, original_size, codec)))
+    }
+    
+    fn add_ref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for &hash in hashes {
+            self.update_ref(hash, |count| count + 1)?;
+        }
+        Ok(())
+    }
+    
+    fn unref(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for &hash in hashes {
+            self.update_ref(hash, |count| count - 1)?;
+        }
+        Ok(())
+    }
+    
+    fn set_ref(&mut self, hash: u128, ref_count: i64) -> Result<(), StoreError> {
+        self.update_ref(hash, |_| ref_count)?;
+        Ok(())
+    }
+    
+    fn replace(&mut self, updates: &[(u128, Vec<u8>, Codec)]) -> Result<(), StoreError> {
+        for (hash, data, codec) in updates {
+            let Ok(mut file) = File::open(self.path(*hash)) else {
+                continue;
+            };
+            let (ref_count, original_size, _) = Self::read_header(&mut file)?;
+            drop(file);
+            self.write(*hash, ref_count, original_size, *codec, data)?;
+        }
+        Ok(())
+    }
+    
+    fn remove(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        for &hash in hashes {
+            match fs::remove_file(self.path(hash)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    
+    fn sweep(&mut self) -> Result<(usize, u64), StoreError> {
+        let (mut count, mut size) = (0, 0);
+        for (_, path) in self.files()? {
+            let mut file = File::open(&path)?;
+            let (ref_count, _, _) = Self::read_header(&mut file)?;
+            if ref_count <= 0 {
+                size += file.metadata()?.len().saturating_sub(HEADER_SIZE as u64);
+                drop(file);
+                fs::remove_file(&path)?;
+                count += 1;
+            }
+        }
+        Ok((count, size))
+    }
+    
+    fn for_each(&self, f: &mut dyn FnMut(&ChunkEntry) -> Result<(), StoreError>) -> Result<(), StoreError> {
+        for (hash, path) in self.files()? {
+            let mut file = File::open(&path)?;
+            let (ref_count, original_size, codec) = Self::read_header(&mut file)?;
+            f(&ChunkEntry {
+                hash,
+                original_size,
+                stored_size: file.metadata()?.len().saturating_sub(HEADER_SIZE as u64),
+                ref_count,
+                codec,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::prepare_chunks_parallel;
+    use crate::{ChunkStore, ModArchive};
+    use tempfile::tempdir;
+    
+    /// 所有后端的引用计数与回收行为一致
+    fn exercise(backend: &mut dyn ChunkBackend) {
+        let a: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+        let b: Vec<u8> = (0..4096u32).map(|i| (i % 11) as u8).collect();
+        let chunks = prepare_chunks_parallel(vec![&a[..], &b[..]], 3).unwrap();
+        
+        let stored = backend.put(&chunks).unwrap();
+        assert_eq!(stored, chunks.iter().map(|c| c.compressed.len() as u64).sum::<u64>());
+        assert_eq!(backend.put(&chunks[..1]).unwrap(), 0);
+        
+        let (data, size, codec) = backend.get(chunks[0].hash).unwrap().unwrap();
+        assert_eq!((data, size, codec), (chunks[0].compressed.clone(), 4096, Codec::Zstd));
+        let many = backend.get_many(&[chunks[1].hash, 7, chunks[0].hash]).unwrap();
+        assert_eq!(many.len(), 2);
+        assert_eq!(many[&chunks[1].hash], (chunks[1].compressed.clone(), 4096, Codec::Zstd));
+        
+        backend.unref(&[chunks[0].hash, chunks[1].hash]).unwrap();
+        let mut refs = HashMap::new();
+        backend.for_each(&mut |entry| {
+            refs.insert(entry.hash, entry.ref_count);
+            Ok(())
+        }).unwrap();
+        assert_eq!(refs, HashMap::from([(chunks[0].hash, 1), (chunks[1].hash, 0)]));
+        
+        assert_eq!(backend.sweep().unwrap(), (1, chunks[1].compressed.len() as u64));
+        assert!(backend.get(chunks[1].hash).unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_backends_agree() {
+        let dir = tempdir().unwrap();
+        exercise(&mut MemoryBackend::new());
+        exercise(&mut DirectoryBackend::open(dir.path().join("chunks")).unwrap());
+        let store = ChunkStore::open(dir.path().join("sqlite")).unwrap();
+        exercise(&mut SqliteBackend::new(store.conn.clone(), Path::new(store.base_path()), BackendKind::Sqlite));
+        let store = ChunkStore::open(dir.path().join("pack")).unwrap();
+        exercise(&mut SqliteBackend::new(store.conn.clone(), Path::new(store.base_path()), BackendKind::Pack));
+    }
+    
+    #[test]
+    fn test_archive_with_memory_backend() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(src.path().join("a.dat"), &data).unwrap();
+        fs::write(src.path().join("b.dat"), &data).unwrap();
+        
+        let mut archive = ModArchive::with_backend(store.path(), Box::new(MemoryBackend::new())).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        let stats = archive.get_stats().unwrap();
+        assert_eq!(stats.unique_chunks, 5);
+        let chunk_rows: i64 = archive.store.conn
+            .query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(chunk_rows, 0);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("b.dat")).unwrap(), data);
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        archive.remove_mod("m").unwrap();
+        assert_eq!(archive.gc().unwrap().0, 5);
+    }
+}
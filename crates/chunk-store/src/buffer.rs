@@ -0,0 +1,295 @@
+//! 顶点/索引缓冲区（buf/ib）
+//!
+//! buf/ib 文件和其他文件一样经过块存储去重。3DMigoto 的 ini 在资源段中声明元素大小：
+//!
+//! ```ini
+//! [ResourceBodyVB]
+//! type = Buffer
+//! stride = 40
+//! filename = Body.buf
+//!
+//! [ResourceBodyIB]
+//! type = Buffer
+//! format = DXGI_FORMAT_R16_UINT
+//! filename = Body.ib
+//! ```
+//!
+//! 已知元素大小时块边界按元素对齐。旧版存储把 buf/ib 整体压缩到
+//! `compressed/{mod_id}_{hash64}.zst`，由 schema 迁移原地转换为块。
+
+use rusqlite::Transaction;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{parse_manifest, ModManifest};
+use crate::backend::insert_chunks;
+use crate::chunk::{decompress_chunk, hash_chunk, prepare_chunks_parallel, ChunkConfig, ChunkingStrategy};
+use crate::journal::FileOp;
+use crate::schema;
+use crate::store::{insert_pending_op, StoreError};
+
+/// 缓冲区在 stride 表中的 key：相对 mod 根目录的小写路径，分隔符统一为 `/`
+pub fn buffer_key(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    let path = path.replace('\\', "/").to_lowercase();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// 解析一个 ini 中声明的缓冲区元素大小，`ini_dir` 为 ini 所在目录（相对 mod 根目录）
+pub fn parse_strides(ini: &str, ini_dir: &str) -> HashMap<String, usize> {
+    let mut strides = HashMap::new();
+    let mut stride = None;
+    let mut filename: Option<String> = None;
+    
+    let mut flush = |stride: &mut Option<usize>, filename: &mut Option<String>| {
+        if let (Some(s), Some(f)) = (stride.take(), filename.take()) {
+            strides.insert(buffer_key(&format!("{}/{}", ini_dir, f)), s);
+        }
+    };
+    
+    for line in ini.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            flush(&mut stride, &mut filename);
+            continue;
+        }
+        if line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_lowercase().as_str() {
+            "stride" => stride = value.parse().ok().filter(|&s| s > 0),
+            "format" => match value.to_uppercase().as_str() {
+                "DXGI_FORMAT_R16_UINT" => stride = Some(2),
+                "DXGI_FORMAT_R32_UINT" => stride = Some(4),
+                _ => {}
+            },
+            "filename" => filename = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    flush(&mut stride, &mut filename);
+    
+    strides
+}
+
+/// 读取 mod 中所有 ini 声明的缓冲区元素大小（ini 无法读取时忽略）
+pub fn load_strides<'a>(root: &Path, files: impl IntoIterator<Item = &'a String>) -> HashMap<String, usize> {
+    let mut strides = HashMap::new();
+    for relative_path in files {
+        if !relative_path.to_lowercase().ends_with(".ini") {
+            continue;
+        }
+        let Ok(bytes) = fs::read(root.join(relative_path)) else {
+            continue;
+        };
+        let ini_dir = Path::new(relative_path).parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        strides.extend(parse_strides(&String::from_utf8_lossy(&bytes), &ini_dir));
+    }
+    strides
+}
+
+/// 缓冲区的分块策略：已知元素大小时按元素对齐，否则使用默认策略
+pub fn buffer_strategy(
+    strides: &HashMap<String, usize>,
+    relative_path: &str,
+    default: ChunkingStrategy,
+) -> ChunkingStrategy {
+    match strides.get(&buffer_key(relative_path)) {
+        Some(&stride) => ChunkingStrategy::Strided { size: default.chunk_size(), stride },
+        None => default,
+    }
+}
+
+/// schema 迁移：把 `compressed/*.zst` 中的旧版 buf/ib 转换为块
+///
+/// 在迁移事务中写入块、更新清单，并登记删除已转换文件的日志，
+/// 文件在事务提交后由日志恢复删除。缺失或损坏的文件保持旧格式。
+pub(crate) fn migrate_compressed(tx: &Transaction, base: &Path) -> Result<(), StoreError> {
+    let chunk_size = schema::get_meta(tx, "chunk_size")?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| ChunkConfig::default().chunk_size());
+    let default = ChunkingStrategy::Fixed { size: chunk_size };
+    let level = ChunkConfig::default().compression_level;
+    
+    let rows: Vec<(String, Option<i64>, String)> = {
+        let mut stmt = tx.prepare(
+            "SELECT id, NULL, manifest FROM mods
+             UNION ALL SELECT id, version, manifest FROM mod_versions"
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    
+    let mut converted: HashSet<String> = HashSet::new();
+    for (id, version, manifest_json) in rows {
+        // 无法解析的清单留给 fsck 报告
+        let Ok(mut manifest) = parse_manifest(&id, &manifest_json) else {
+            continue;
+        };
+        if manifest.compressed_ids().next().is_none() {
+            continue;
+        }
+        
+        let preserved_dir = match version {
+            Some(v) => base.join("versions").join(&id).join(v.to_string()),
+            None => base.join("mods").join(&id),
+        };
+        let strides = load_strides(&preserved_dir, &manifest.preserved_files);
+        
+        let mut changed = false;
+        for file in manifest.files.iter_mut().filter(|f| f.is_compressed()) {
+            let Some(file_id) = file.chunks.first().cloned() else {
+                continue;
+            };
+            let Ok(compressed) = fs::read(base.join("compressed").join(format!("{}.zst", file_id))) else {
+                continue;
+            };
+            let Ok(data) = decompress_chunk(&compressed, file.original_size as usize) else {
+                continue;
+            };
+            
+            let strategy = buffer_strategy(&strides, &file.path, default);
+            let prepared = prepare_chunks_parallel(strategy.split(&data), level)?;
+            manifest.stored_size = (manifest.stored_size + insert_chunks(tx, None, &prepared)?)
+                .saturating_sub(compressed.len() as u64);
+            
+            file.chunks = prepared.iter().map(|c| format!("{:032x}", c.hash)).collect();
+            file.checksum.get_or_insert_with(|| format!("{:032x}", hash_chunk(&data)));
+            file.chunking = Some(strategy);
+            converted.insert(file_id);
+            changed = true;
+        }
+        if !changed {
+            continue;
+        }
+        
+        let json = serialize(&manifest)?;
+        match version {
+            Some(v) => tx.execute(
+                "UPDATE mod_versions SET manifest = ? WHERE id = ? AND version = ?",
+                rusqlite::params![json, id, v],
+            )?,
+            None => tx.execute("UPDATE mods SET manifest = ? WHERE id = ?", [&json, &id])?,
+        };
+    }
+    
+    if !converted.is_empty() {
+        let ops: Vec<FileOp> = converted.iter()
+            .map(|file_id| FileOp::Remove {
+                path: PathBuf::from("compressed").join(format!("{}.zst", file_id)),
+            })
+            .collect();
+        let plan = serde_json::to_string(&ops).map_err(|e| StoreError::CorruptJournal(e.to_string()))?;
+        insert_pending_op(tx, ("migrate-compressed", &plan))?;
+    }
+    
+    Ok(())
+}
+
+fn serialize(manifest: &ModManifest) -> Result<String, StoreError> {
+    serde_json::to_string(manifest).map_err(|e| StoreError::CorruptManifest(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChunkStore, ModArchive};
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_parse_strides() {
+        let ini = "\
+[ResourceBodyVB]
+type = Buffer
+stride = 40
+filename = Body.buf
+
+; 注释
+[ResourceBodyIB]
+type = Buffer
+format = DXGI_FORMAT_R16_UINT
+filename = ..\\shared\\Body.ib
+
+[ResourceTexture]
+filename = Diffuse.dds
+";
+        let strides = parse_strides(ini, "Character");
+        assert_eq!(strides.get("character/body.buf"), Some(&40));
+        assert_eq!(strides.get("shared/body.ib"), Some(&2));
+        assert_eq!(strides.len(), 2);
+        
+        let strategy = buffer_strategy(&strides, "Character/Body.buf", ChunkingStrategy::Fixed { size: 4096 });
+        assert_eq!(strategy, ChunkingStrategy::Strided { size: 4096, stride: 40 });
+        let data = vec![0u8; 10000];
+        assert!(strategy.split(&data).iter().all(|c| c.len() % 40 == 0));
+    }
+    
+    #[test]
+    fn test_migrate_legacy_compressed_buffer() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let vertices: Vec<u8> = (0..10000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(src.path().join("mod.ini"), "[ResourceVB]\nstride = 40\nfilename = body.buf\n").unwrap();
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        drop(archive);
+        
+        // 改写为旧版存储：buf 整体压缩到 compressed/，schema 版本 2
+        let conn = rusqlite::Connection::open(store.path().join("store.db")).unwrap();
+        conn.execute_batch(
+            "DROP TABLE dictionaries;
+             DROP TABLE packs;
+             DROP INDEX idx_chunks_pack;
+             ALTER TABLE chunks DROP COLUMN pack_id;
+             ALTER TABLE chunks DROP COLUMN pack_offset;
+             ALTER TABLE chunks DROP COLUMN pack_len;
+             ALTER TABLE chunks DROP COLUMN dict_id;
+             ALTER TABLE quarantined_chunks DROP COLUMN dict_id;
+             ALTER TABLE chunks DROP COLUMN codec;
+             ALTER TABLE quarantined_chunks DROP COLUMN codec;"
+        ).unwrap();
+        let json: String = conn.query_row("SELECT manifest FROM mods WHERE id = 'm'", [], |r| r.get(0)).unwrap();
+        let mut manifest: serde_json::Value = serde_json::from_str(&json).unwrap();
+        manifest["files"] = serde_json::json!([{
+            "path": "body.buf",
+            "original_size": vertices.len(),
+            "chunks": ["m_0000000000000001"],
+            "file_type": "buf",
+        }]);
+        conn.execute("UPDATE mods SET manifest = ?", [manifest.to_string()]).unwrap();
+        conn.execute("UPDATE meta SET value = '2' WHERE key = 'schema_version'", []).unwrap();
+        drop(conn);
+        let zst = store.path().join("compressed").join("m_0000000000000001.zst");
+        fs::create_dir_all(zst.parent().unwrap()).unwrap();
+        fs::write(&zst, zstd::bulk::compress(&vertices, 3).unwrap()).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        assert!(!zst.exists());
+        assert_eq!(archive.store.meta("schema_version").unwrap(), Some(schema::schema_version().to_string()));
+        
+        let manifest = archive.load_manifest("m").unwrap().unwrap();
+        assert_eq!(manifest.files[0].chunking, Some(ChunkingStrategy::Strided { size: 4096, stride: 40 }));
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("body.buf")).unwrap(), vertices);
+        assert!(archive.fsck(false).unwrap().is_clean());
+        assert!(ChunkStore::open(store.path()).is_ok());
+    }
+}
@@ -1,22 +1,173 @@
 //! 块处理模块
 
+use fastcdc::v2020::FastCDC;
 use rayon::prelude::*;
-use xxhash_rust::xxh3::xxh3_128;
-use zstd::bulk::{compress, decompress};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+use zstd::bulk::{compress, decompress, Compressor, Decompressor};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::store::StoreError;
+
+/// 分块策略，随每个文件记录在清单中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkingStrategy {
+    /// 固定大小分块
+    Fixed { size: usize },
+    /// 内容定义分块（FastCDC 滚动 hash），插入或删除少量字节只影响附近的块边界
+    ContentDefined { min_size: usize, avg_size: usize, max_size: usize },
+    /// DDS 按 mip 层级和块行对齐（见 `dds::split_payload`），没有纹理布局时等同固定分块
+    MipAligned { size: usize },
+    /// buf/ib 按元素大小对齐：每块包含整数个顶点或索引
+    Strided { size: usize, stride: usize },
+}
+
+impl ChunkingStrategy {
+    /// 以 `avg_size` 为平均块大小的内容定义分块（最小 1/4，最大 4 倍）
+    pub fn content_defined(avg_size: usize) -> Self {
+        Self::ContentDefined {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size * 4,
+        }
+    }
+    
+    /// 固定块大小或平均块大小
+    pub fn chunk_size(&self) -> usize {
+        match *self {
+            Self::Fixed { size } | Self::MipAligned { size } | Self::Strided { size, .. } => size,
+            Self::ContentDefined { avg_size, .. } => avg_size,
+        }
+    }
+    
+    /// 检查参数是否在允许范围内
+    pub fn validate(&self) -> Result<(), StoreError> {
+        let valid = match *self {
+            Self::Fixed { size } | Self::MipAligned { size } => size > 0,
+            Self::Strided { size, stride } => size > 0 && stride > 0,
+            Self::ContentDefined { min_size, avg_size, max_size } => {
+                use fastcdc::v2020::{AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN};
+                (MINIMUM_MIN as usize..=MINIMUM_MAX as usize).contains(&min_size)
+                    && (AVERAGE_MIN as usize..=AVERAGE_MAX as usize).contains(&avg_size)
+                    && (MAXIMUM_MIN as usize..=MAXIMUM_MAX as usize).contains(&max_size)
+                    && min_size <= avg_size
+                    && avg_size <= max_size
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(StoreError::InvalidConfig(format!("invalid chunking strategy: {:?}", self)))
+        }
+    }
+    
+    /// 按策略分割数据（调用前需 `validate`）
+    pub fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        match *self {
+            Self::Fixed { size } | Self::MipAligned { size } => chunk_data(data, size),
+            Self::Strided { size, stride } => chunk_data(data, (size / stride).max(1) * stride),
+            Self::ContentDefined { min_size, avg_size, max_size } => {
+                FastCDC::new(data, min_size as u32, avg_size as u32, max_size as u32)
+                    .map(|c| &data[c.offset..c.offset + c.length])
+                    .collect()
+            }
+        }
+    }
+}
+
+/// 块的编码方式，随每个块保存
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// 未压缩（压缩后不比原始数据小，例如 BC7 纹理）
+    Raw,
+    #[default]
+    Zstd,
+    /// 使用 `dictionaries` 表中该 id 的字典压缩
+    ZstdDict(i64),
+    /// 压缩率低于 zstd，解压更快
+    Lz4,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Raw => "raw",
+            Codec::Zstd => "zstd",
+            Codec::ZstdDict(_) => "zstd-dict",
+            Codec::Lz4 => "lz4",
+        }
+    }
+    
+    /// 由 `name` 还原（不含需要字典 id 的 `zstd-dict`）
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raw" => Some(Codec::Raw),
+            "zstd" => Some(Codec::Zstd),
+            "lz4" => Some(Codec::Lz4),
+            _ => None,
+        }
+    }
+    
+    pub fn dict_id(&self) -> Option<i64> {
+        match self {
+            Codec::ZstdDict(id) => Some(*id),
+            _ => None,
+        }
+    }
+    
+    /// `chunks.codec` 列的值
+    pub(crate) fn tag(&self) -> i64 {
+        match self {
+            Codec::Raw => 0,
+            Codec::Zstd => 1,
+            Codec::ZstdDict(_) => 2,
+            Codec::Lz4 => 3,
+        }
+    }
+    
+    /// 由 `codec` 和 `dict_id` 列还原；v8 之前的块没有 codec 列，按是否有字典区分
+    pub(crate) fn from_columns(tag: Option<i64>, dict_id: Option<i64>) -> Result<Self, StoreError> {
+        match (tag, dict_id) {
+            (Some(0), _) => Ok(Codec::Raw),
+            (Some(3), _) => Ok(Codec::Lz4),
+            (Some(1) | Some(2) | None, Some(id)) => Ok(Codec::ZstdDict(id)),
+            (Some(1) | None, None) => Ok(Codec::Zstd),
+            (Some(tag), _) => Err(StoreError::Compression(format!("unknown codec tag: {}", tag))),
+        }
+    }
+}
 
 /// 块配置
+#[derive(Debug, Clone)]
 pub struct ChunkConfig {
-    /// 块大小（字节）
-    pub chunk_size: usize,
+    /// 分块策略
+    pub strategy: ChunkingStrategy,
+    /// 默认编码：`Zstd` 或 `Lz4`（有字典的类别总是使用字典压缩）
+    pub codec: Codec,
     /// zstd 压缩级别 (1-22, 默认 3)
     pub compression_level: i32,
+    /// 块的 hash 算法（由存储决定，更换需要 `rehash`）
+    pub hash_algorithm: HashAlgorithm,
+    /// DDS 纹理按 mip 层级和块行对齐分块（无法识别格式时使用 `strategy`）
+    pub dds_aware: bool,
+}
+
+impl ChunkConfig {
+    /// 固定块大小或 CDC 的平均块大小
+    pub fn chunk_size(&self) -> usize {
+        self.strategy.chunk_size()
+    }
 }
 
 impl Default for ChunkConfig {
     fn default() -> Self {
         Self {
-            chunk_size: 4096, // 4KB
+            strategy: ChunkingStrategy::Fixed { size: 4096 }, // 4KB
+            codec: Codec::Zstd,
             compression_level: 3, // 快速压缩
+            hash_algorithm: HashAlgorithm::Xxh3,
+            dds_aware: true,
         }
     }
 }
@@ -27,6 +178,82 @@ pub fn hash_chunk(data: &[u8]) -> u128 {
     xxh3_128(data)
 }
 
+/// 块和文件校验和使用的 hash 算法，记录在 `meta.hash_algorithm` 和每个清单中
+///
+/// 两种算法都输出 128 位，块的 hash 列格式不变。xxh3 足以应对本地数据中的偶然碰撞；
+/// 块来自不可信的来源时改用 BLAKE3（截断为 128 位），无法有意构造碰撞。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "xxh3-128")]
+    Xxh3,
+    #[serde(rename = "blake3-128")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Xxh3 => "xxh3-128",
+            HashAlgorithm::Blake3 => "blake3-128",
+        }
+    }
+    
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xxh3-128" => Some(HashAlgorithm::Xxh3),
+            "blake3-128" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+    
+    /// 计算数据的 hash
+    pub fn hash(&self, data: &[u8]) -> u128 {
+        match self {
+            HashAlgorithm::Xxh3 => xxh3_128(data),
+            HashAlgorithm::Blake3 => blake3_128(blake3::hash(data)),
+        }
+    }
+    
+    /// 分段计算 hash 的状态（整文件校验和）
+    pub fn hasher(&self) -> ChunkHasher {
+        match self {
+            HashAlgorithm::Xxh3 => ChunkHasher::Xxh3(Box::new(Xxh3::new())),
+            HashAlgorithm::Blake3 => ChunkHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// BLAKE3 输出的前 128 位
+fn blake3_128(hash: blake3::Hash) -> u128 {
+    let bytes: [u8; 16] = hash.as_bytes()[..16].try_into().expect("BLAKE3 outputs 32 bytes");
+    u128::from_le_bytes(bytes)
+}
+
+/// `HashAlgorithm::hasher` 返回的增量 hash 状态
+pub enum ChunkHasher {
+    Xxh3(Box<Xxh3>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ChunkHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChunkHasher::Xxh3(hasher) => hasher.update(data),
+            ChunkHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+    
+    pub fn finish(&self) -> u128 {
+        match self {
+            ChunkHasher::Xxh3(hasher) => hasher.digest128(),
+            ChunkHasher::Blake3(hasher) => blake3_128(hasher.finalize()),
+        }
+    }
+}
+
 /// 将数据分割成块
 pub fn chunk_data(data: &[u8], chunk_size: usize) -> Vec<&[u8]> {
     data.chunks(chunk_size).collect()
@@ -38,37 +265,169 @@ pub struct PreparedChunk {
     pub hash: u128,
     pub compressed: Vec<u8>,
     pub original_size: usize,
+    pub codec: Codec,
+}
+
+/// 按压缩级别预处理的 zstd 压缩字典
+pub struct ChunkDictionary {
+    pub id: i64,
+    encoder: EncoderDictionary<'static>,
+}
+
+impl ChunkDictionary {
+    pub fn new(id: i64, data: &[u8], compression_level: i32) -> Self {
+        Self {
+            id,
+            encoder: EncoderDictionary::copy(data, compression_level),
+        }
+    }
+    
+    /// 用字典压缩数据
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, StoreError> {
+        Compressor::with_prepared_dictionary(&self.encoder)
+            .and_then(|mut c| c.compress(data))
+            .map_err(|e| StoreError::Compression(e.to_string()))
+    }
 }
 
 /// 并行处理块（hash + 压缩）
 pub fn prepare_chunks_parallel(
     chunks: Vec<&[u8]>,
     compression_level: i32,
-) -> Vec<PreparedChunk> {
+) -> Result<Vec<PreparedChunk>, StoreError> {
+    prepare_chunks_with(chunks.into_iter().map(|c| (c, None)).collect(), compression_level)
+}
+
+/// 并行处理块，每个块可以指定压缩字典
+///
+/// 总是使用 zstd，不回退到未压缩：schema 迁移写入的块不能依赖之后才加入的 `codec` 列。
+pub fn prepare_chunks_with(
+    chunks: Vec<(&[u8], Option<&ChunkDictionary>)>,
+    compression_level: i32,
+) -> Result<Vec<PreparedChunk>, StoreError> {
     chunks
         .into_par_iter()
-        .map(|chunk| {
-            let hash = hash_chunk(chunk);
-            let compressed = compress(chunk, compression_level)
-                .expect("compression failed");
-            PreparedChunk {
-                hash,
+        .map(|(chunk, dict)| {
+            let compressed = match dict {
+                Some(dict) => dict.compress(chunk)?,
+                None => compress(chunk, compression_level)
+                    .map_err(|e| StoreError::Compression(e.to_string()))?,
+            };
+            Ok(PreparedChunk {
+                hash: hash_chunk(chunk),
                 compressed,
                 original_size: chunk.len(),
-            }
+                codec: dict.map_or(Codec::Zstd, |d| Codec::ZstdDict(d.id)),
+            })
+        })
+        .collect()
+}
+
+/// 并行处理块，没有字典的块使用 `codec` 编码
+pub fn prepare_chunks_encoded(
+    chunks: Vec<(&[u8], Option<&ChunkDictionary>)>,
+    codec: Codec,
+    compression_level: i32,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<PreparedChunk>, StoreError> {
+    chunks
+        .into_par_iter()
+        .map(|(chunk, dict)| {
+            let (compressed, codec) = encode_chunk(chunk, dict, codec, compression_level)?;
+            Ok(PreparedChunk {
+                hash: hash_algorithm.hash(chunk),
+                compressed,
+                original_size: chunk.len(),
+                codec,
+            })
         })
         .collect()
 }
 
+/// 编码块；压缩后不比原始数据小时原样保存
+pub fn encode_chunk(
+    data: &[u8],
+    dict: Option<&ChunkDictionary>,
+    codec: Codec,
+    compression_level: i32,
+) -> Result<(Vec<u8>, Codec), StoreError> {
+    let (encoded, codec) = match (dict, codec) {
+        (Some(dict), _) => (dict.compress(data)?, Codec::ZstdDict(dict.id)),
+        (None, Codec::Raw) => return Ok((data.to_vec(), Codec::Raw)),
+        (None, Codec::Lz4) => (lz4_flex::compress(data), Codec::Lz4),
+        (None, _) => (
+            compress(data, compression_level).map_err(|e| StoreError::Compression(e.to_string()))?,
+            Codec::Zstd,
+        ),
+    };
+    if encoded.len() >= data.len() {
+        Ok((data.to_vec(), Codec::Raw))
+    } else {
+        Ok((encoded, codec))
+    }
+}
+
+/// 解码块（字典编码的块需要提供对应的解压字典）
+pub fn decode_chunk(
+    data: &[u8],
+    original_size: usize,
+    codec: Codec,
+    dict: Option<&DecoderDictionary<'static>>,
+) -> Result<Vec<u8>, StoreError> {
+    match (codec, dict) {
+        (Codec::Raw, _) if data.len() == original_size => Ok(data.to_vec()),
+        (Codec::Raw, _) => Err(StoreError::Compression(format!(
+            "raw chunk is {} bytes, expected {}", data.len(), original_size
+        ))),
+        (Codec::Zstd, _) => decompress_chunk(data, original_size),
+        (Codec::ZstdDict(_), Some(dict)) => decompress_chunk_with(data, original_size, dict),
+        (Codec::ZstdDict(id), None) => Err(StoreError::DictionaryNotFound(id.to_string())),
+        (Codec::Lz4, _) => {
+            let decoded = lz4_flex::decompress(data, original_size)
+                .map_err(|e| StoreError::Compression(e.to_string()))?;
+            if decoded.len() != original_size {
+                return Err(StoreError::Compression(format!(
+                    "decompressed {} bytes, expected {}", decoded.len(), original_size
+                )));
+            }
+            Ok(decoded)
+        }
+    }
+}
+
 /// 解压块
-pub fn decompress_chunk(compressed: &[u8], original_size: usize) -> Vec<u8> {
-    decompress(compressed, original_size).expect("decompression failed")
+pub fn decompress_chunk(compressed: &[u8], original_size: usize) -> Result<Vec<u8>, StoreError> {
+    let data = decompress(compressed, original_size)
+        .map_err(|e| StoreError::Compression(e.to_string()))?;
+    if data.len() != original_size {
+        return Err(StoreError::Compression(format!(
+            "decompressed {} bytes, expected {}", data.len(), original_size
+        )));
+    }
+    Ok(data)
+}
+
+/// 用字典解压块
+pub fn decompress_chunk_with(
+    compressed: &[u8],
+    original_size: usize,
+    dict: &DecoderDictionary<'static>,
+) -> Result<Vec<u8>, StoreError> {
+    let data = Decompressor::with_prepared_dictionary(dict)
+        .and_then(|mut d| d.decompress(compressed, original_size))
+        .map_err(|e| StoreError::Compression(e.to_string()))?;
+    if data.len() != original_size {
+        return Err(StoreError::Compression(format!(
+            "decompressed {} bytes, expected {}", data.len(), original_size
+        )));
+    }
+    Ok(data)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
+    
     #[test]
     fn test_chunk_data() {
         let data = vec![0u8; 10000];
@@ -78,14 +437,44 @@ mod tests {
         assert_eq!(chunks[1].len(), 4096);
         assert_eq!(chunks[2].len(), 1808);
     }
-
+    
+    #[test]
+    fn test_content_defined_resyncs_after_insert() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let mut shifted = vec![0xAB; 7];
+        shifted.extend_from_slice(&data);
+        
+        let strategy = ChunkingStrategy::content_defined(4096);
+        strategy.validate().unwrap();
+        let original: std::collections::HashSet<u128> = strategy.split(&data).iter().map(|c| hash_chunk(c)).collect();
+        let chunks = strategy.split(&shifted);
+        assert_eq!(chunks.concat(), shifted);
+        
+        // 只有开头的块受插入影响
+        let reused = chunks.iter().filter(|c| original.contains(&hash_chunk(c))).count();
+        assert!(reused + 2 >= chunks.len(), "{} of {} reused", reused, chunks.len());
+        
+        assert!(ChunkingStrategy::content_defined(16).validate().is_err());
+    }
+    
     #[test]
     fn test_hash_chunk() {
         let data = b"hello world";
         let hash = hash_chunk(data);
         assert_ne!(hash, 0);
+        assert_eq!(HashAlgorithm::Xxh3.hash(data), hash);
+        assert_ne!(HashAlgorithm::Blake3.hash(data), hash);
+        
+        // 分段计算与整体计算一致
+        for algorithm in [HashAlgorithm::Xxh3, HashAlgorithm::Blake3] {
+            let mut hasher = algorithm.hasher();
+            hasher.update(&data[..5]);
+            hasher.update(&data[5..]);
+            assert_eq!(hasher.finish(), algorithm.hash(data));
+            assert_eq!(HashAlgorithm::from_name(algorithm.name()), Some(algorithm));
+        }
     }
-
+    
     #[test]
     fn test_compress_decompress() {
         let data = vec![0u8; 4096];
@@ -93,4 +482,36 @@ mod tests {
         let decompressed = decompress(&compressed, data.len()).unwrap();
         assert_eq!(data, decompressed);
     }
+    
+    #[test]
+    fn test_codec_selection() {
+        let compressible = vec![0u8; 4096];
+        let random: Vec<u8> = (0..4096u32).map(|i| (hash_chunk(&i.to_le_bytes()) >> 64) as u8).collect();
+        
+        for codec in [Codec::Zstd, Codec::Lz4, Codec::Raw] {
+            let (encoded, used) = encode_chunk(&compressible, None, codec, 3).unwrap();
+            assert_eq!(used, codec);
+            assert_eq!(decode_chunk(&encoded, 4096, used, None).unwrap(), compressible);
+            
+            // 不可压缩的数据原样保存
+            let (encoded, used) = encode_chunk(&random, None, codec, 3).unwrap();
+            assert_eq!((used, encoded.len()), (Codec::Raw, 4096));
+            assert_eq!(decode_chunk(&encoded, 4096, used, None).unwrap(), random);
+        }
+        
+        assert_eq!(Codec::from_columns(None, Some(5)).unwrap(), Codec::ZstdDict(5));
+        assert_eq!(Codec::from_columns(None, None).unwrap(), Codec::Zstd);
+        assert!(Codec::from_columns(Some(9), None).is_err());
+    }
+    
+    #[test]
+    fn test_decompress_corrupt_chunk() {
+        assert!(matches!(
+            decompress_chunk(b"not zstd", 4096),
+            Err(StoreError::Compression(_))
+        ));
+        
+        let compressed = compress(&[7u8; 100], 3).unwrap();
+        assert!(decompress_chunk(&compressed, 4096).is_err());
+    }
 }
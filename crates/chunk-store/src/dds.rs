@@ -1,12 +1,29 @@
 //! DDS 文件解析
 
+/// 像素数据的存储方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// BCn 压缩：每个 4x4 像素块的字节数
+    Block { bytes: usize },
+    /// 未压缩：每像素位数
+    Linear { bits: usize },
+}
+
 /// DDS 文件头信息
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DdsMetadata {
     pub header_size: usize,
     pub width: u32,
     pub height: u32,
     pub format: String,
+    /// mip 层级数（至少为 1）
+    pub mip_count: u32,
+    /// 表面数（数组大小 × 立方体面数）
+    pub array_size: u32,
+    /// 体积纹理的深度（普通纹理为 1）
+    pub depth: u32,
+    /// 无法识别的格式为 `None`
+    pub layout: Option<PixelLayout>,
 }
 
 /// 解析 DDS 文件头
@@ -20,20 +37,36 @@ pub fn parse_dds_header(data: &[u8]) -> Option<DdsMetadata> {
         return None;
     }
     
-    let height = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
-    let width = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
+    let read_u32 = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    
+    let flags = read_u32(8);
+    let height = read_u32(12);
+    let width = read_u32(16);
+    let depth = if flags & DDSD_DEPTH != 0 { read_u32(24).max(1) } else { 1 };
+    let mip_count = read_u32(28).max(1);
+    let cube_faces = if read_u32(112) & DDSCAPS2_CUBEMAP != 0 { 6 } else { 1 };
     
     // 检查是否有 DX10 扩展头
     let has_dx10 = data.len() >= 148 && &data[84..88] == b"DX10";
     let header_size = if has_dx10 { 148 } else { 128 };
     
     // 获取格式
-    let format = if has_dx10 {
-        let dxgi_format = u32::from_le_bytes([data[128], data[129], data[130], data[131]]);
-        format!("DXGI_{}", dxgi_format)
+    let (format, layout, array_size) = if has_dx10 {
+        let dxgi_format = read_u32(128);
+        let cube_faces = if read_u32(136) & DDS_RESOURCE_MISC_TEXTURECUBE != 0 { 6 } else { 1 };
+        let array_size = read_u32(140).max(1) * cube_faces;
+        (format!("DXGI_{}", dxgi_format), dxgi_layout(dxgi_format), array_size)
     } else {
         let fourcc = &data[84..88];
-        String::from_utf8_lossy(fourcc).to_string()
+        let layout = if read_u32(80) & DDPF_FOURCC != 0 {
+            fourcc_layout(fourcc)
+        } else {
+            match read_u32(88) {
+                0 => None,
+                bits => Some(PixelLayout::Linear { bits: bits as usize }),
+            }
+        };
+        (String::from_utf8_lossy(fourcc).to_string(), layout, cube_faces)
     };
     
     Some(DdsMetadata {
@@ -41,17 +74,123 @@ pub fn parse_dds_header(data: &[u8]) -> Option<DdsMetadata> {
         width,
         height,
         format,
+        mip_count,
+        array_size,
+        depth,
+        layout,
     })
 }
 
+const DDSD_DEPTH: u32 = 0x800000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDPF_FOURCC: u32 = 0x4;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// 旧式文件头的 FourCC 格式
+fn fourcc_layout(fourcc: &[u8]) -> Option<PixelLayout> {
+    match fourcc {
+        b"DXT1" | b"ATI1" | b"BC4U" | b"BC4S" => Some(PixelLayout::Block { bytes: 8 }),
+        b"DXT2" | b"DXT3" | b"DXT4" | b"DXT5" | b"ATI2" | b"BC5U" | b"BC5S" => Some(PixelLayout::Block { bytes: 16 }),
+        // D3DFMT 数值格式
+        _ => match u32::from_le_bytes([fourcc[0], fourcc[1], fourcc[2], fourcc[3]]) {
+            111 => Some(PixelLayout::Linear { bits: 16 }),       // R16F
+            112 | 114 => Some(PixelLayout::Linear { bits: 32 }), // G16R16F, R32F
+            36 | 110 | 113 | 115 => Some(PixelLayout::Linear { bits: 64 }),
+            116 => Some(PixelLayout::Linear { bits: 128 }),      // A32B32G32R32F
+            _ => None,
+        },
+    }
+}
+
+/// DXGI_FORMAT 的存储方式（只覆盖 mod 中常见的格式）
+fn dxgi_layout(format: u32) -> Option<PixelLayout> {
+    let layout = match format {
+        70..=72 | 79..=81 => PixelLayout::Block { bytes: 8 },                // BC1, BC4
+        73..=78 | 82..=84 | 94..=99 => PixelLayout::Block { bytes: 16 },     // BC2, BC3, BC5, BC6H, BC7
+        1..=4 => PixelLayout::Linear { bits: 128 },                          // R32G32B32A32
+        5..=8 => PixelLayout::Linear { bits: 96 },                           // R32G32B32
+        9..=22 => PixelLayout::Linear { bits: 64 },                          // R16G16B16A16, R32G32
+        23..=47 | 87..=93 => PixelLayout::Linear { bits: 32 },               // R10G10B10A2, R8G8B8A8, B8G8R8A8 ...
+        48..=59 | 85 | 86 | 115 => PixelLayout::Linear { bits: 16 },         // R8G8, R16, B5G6R5
+        60..=65 => PixelLayout::Linear { bits: 8 },                          // R8, A8
+        _ => return None,
+    };
+    Some(layout)
+}
+
+/// 按 mip 层级和块行对齐分割 DDS 数据（不含文件头）
+///
+/// 大于 `target_size` 的层级按整块行分割；小于 `target_size` 的尾部层级按层级边界
+/// 合并，使只有顶层不同的两个纹理共享所有低层级的块。
+/// 格式无法识别、尺寸溢出或数据长度与计算的布局不符时返回 `None`。
+pub fn split_payload<'a>(metadata: &DdsMetadata, payload: &'a [u8], target_size: usize) -> Option<Vec<&'a [u8]>> {
+    let layout = metadata.layout?;
+    if metadata.depth > 1 || target_size == 0 {
+        return None;
+    }
+    
+    // 每个层级的 (大小, 行字节数, 最小对齐单位)；尺寸来自文件头，构造的文件头可能使乘法溢出
+    let levels: Vec<(usize, usize, usize)> = (0..metadata.mip_count.min(32))
+        .map(|level| {
+            let width = (metadata.width >> level).max(1) as usize;
+            let height = (metadata.height >> level).max(1) as usize;
+            match layout {
+                PixelLayout::Block { bytes } => {
+                    let pitch = width.div_ceil(4).checked_mul(bytes)?;
+                    Some((pitch.checked_mul(height.div_ceil(4))?, pitch, bytes))
+                }
+                PixelLayout::Linear { bits } => {
+                    let pitch = width.checked_mul(bits)?.div_ceil(8);
+                    Some((pitch.checked_mul(height)?, pitch, bits.div_ceil(8)))
+                }
+            }
+        })
+        .collect::<Option<_>>()?;
+    let surface_size = levels.iter().try_fold(0usize, |sum, l| sum.checked_add(l.0))?;
+    let total_size = surface_size.checked_mul(metadata.array_size as usize)?;
+    if surface_size == 0 || total_size != payload.len() {
+        return None;
+    }
+    
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    for _ in 0..metadata.array_size {
+        let mut tail_start = offset;
+        for &(size, pitch, unit) in &levels {
+            if size >= target_size {
+                let step = if pitch <= target_size {
+                    target_size / pitch * pitch
+                } else {
+                    (target_size / unit).max(1) * unit
+                };
+                let level = &payload[offset..offset + size];
+                chunks.extend(level.chunks(step));
+                offset += size;
+                tail_start = offset;
+            } else {
+                if offset > tail_start && offset - tail_start + size > target_size {
+                    chunks.push(&payload[tail_start..offset]);
+                    tail_start = offset;
+                }
+                offset += size;
+            }
+        }
+        if offset > tail_start {
+            chunks.push(&payload[tail_start..offset]);
+        }
+    }
+    
+    Some(chunks)
+}
+
 /// 重建 DDS 文件
-pub fn rebuild_dds(metadata: &DdsMetadata, header: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
-    let total_size: usize = metadata.header_size + chunks.iter().map(|c| c.len()).sum::<usize>();
+pub fn rebuild_dds<C: AsRef<[u8]>>(metadata: &DdsMetadata, header: &[u8], chunks: &[C]) -> Vec<u8> {
+    let total_size: usize = metadata.header_size + chunks.iter().map(|c| c.as_ref().len()).sum::<usize>();
     let mut result = Vec::with_capacity(total_size);
     
     result.extend_from_slice(&header[..metadata.header_size]);
     for chunk in chunks {
-        result.extend_from_slice(chunk);
+        result.extend_from_slice(chunk.as_ref());
     }
     
     result
@@ -60,7 +199,7 @@ pub fn rebuild_dds(metadata: &DdsMetadata, header: &[u8], chunks: &[Vec<u8>]) ->
 #[cfg(test)]
 mod tests {
     use super::*;
-
+    
     #[test]
     fn test_parse_dds_header() {
         // 创建一个最小的 DDS 头
@@ -73,5 +212,46 @@ mod tests {
         assert_eq!(meta.header_size, 128);
         assert_eq!(meta.width, 200);
         assert_eq!(meta.height, 100);
+        assert_eq!(meta.mip_count, 1);
+        assert_eq!(meta.layout, None);
+    }
+    
+    #[test]
+    fn test_split_payload_aligns_to_mips() {
+        // 256x256 BC7，9 个 mip 层级
+        let metadata = DdsMetadata {
+            header_size: 148,
+            width: 256,
+            height: 256,
+            mip_count: 9,
+            array_size: 1,
+            depth: 1,
+            layout: Some(PixelLayout::Block { bytes: 16 }),
+            ..Default::default()
+        };
+        let sizes = [65536, 16384, 4096, 1024, 256, 64, 16, 16, 16];
+        let payload: Vec<u8> = (0..sizes.iter().sum::<usize>()).map(|i| (i % 251) as u8).collect();
+        
+        let chunks = split_payload(&metadata, &payload, 4096).unwrap();
+        assert_eq!(chunks.concat(), payload);
+        
+        // 大层级按 4KB 分割，第 1 个 mip 从新块开始，尾部小层级合并为一个块
+        let lens: Vec<usize> = chunks.iter().map(|c| c.len()).collect();
+        assert_eq!(lens.len(), 16 + 4 + 1 + 1);
+        assert!(lens[..21].iter().all(|&l| l == 4096));
+        assert_eq!(lens[21], 1024 + 256 + 64 + 16 * 3);
+        
+        // 长度与布局不符时不分割
+        assert!(split_payload(&metadata, &payload[1..], 4096).is_none());
+        
+        // 构造的超大尺寸不会溢出
+        let huge = DdsMetadata {
+            width: u32::MAX,
+            height: u32::MAX,
+            mip_count: 32,
+            array_size: u32::MAX,
+            ..metadata
+        };
+        assert!(split_payload(&huge, &payload, 4096).is_none());
     }
 }
@@ -0,0 +1,308 @@
+//! zstd 字典
+//!
+//! 4KB 的块单独压缩时 zstd 无法利用块之间的相似性，按内容类别训练的字典可以弥补。
+//! 类别为 `dds:<格式>`、`buf` 和 `ib`。每次训练生成该类别的新版本字典：
+//! 新块使用最新版本，已有的块重新编码后改用新版本，不再被引用的旧版本随之删除。
+
+use rayon::prelude::*;
+use rusqlite::params;
+use std::collections::{HashMap, HashSet};
+
+use crate::archive::{parse_hash, FileManifest, ModArchive};
+use crate::chunk::{encode_chunk, ChunkDictionary, Codec};
+use crate::store::{chrono_timestamp, ChunkStore, StoreError};
+
+/// 每批重新编码的块数
+const REENCODE_BATCH: usize = 256;
+
+/// 训练字典至少需要的样本数
+const MIN_SAMPLES: usize = 16;
+
+/// DDS 纹理按格式分类
+pub(crate) fn dds_class(format: &str) -> String {
+    format!("dds:{}", format)
+}
+
+/// 文件数据块所属的内容类别（DDS 文件头和通用文件不使用字典）
+pub fn content_class(file: &FileManifest) -> Option<String> {
+    match file.file_type.as_deref() {
+        Some("dds") => file.dds_metadata.as_ref().map(|m| dds_class(&m.format)),
+        Some("buf") | Some("ib") if !file.is_compressed() => file.file_type.clone(),
+        _ => None,
+    }
+}
+
+/// 字典训练选项
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    /// 字典最大字节数
+    pub max_dict_size: usize,
+    /// 每个类别最多使用的样本块数
+    pub max_samples: usize,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        Self {
+            max_dict_size: 110 * 1024,
+            max_samples: 4096,
+        }
+    }
+}
+
+/// 单个类别的训练结果
+#[derive(Debug, Default)]
+pub struct TrainReport {
+    pub class: String,
+    /// 新字典的 id（未训练时为 `None`）
+    pub dict_id: Option<i64>,
+    pub dict_size: usize,
+    pub samples: usize,
+    /// 改用新字典的块数
+    pub chunks_reencoded: usize,
+    /// 该类别所有块重新编码前后的存储大小
+    pub size_before: u64,
+    pub size_after: u64,
+    /// 跳过训练的原因
+    pub note: Option<String>,
+}
+
+impl ChunkStore {
+    /// 每个类别的最新字典
+    pub(crate) fn latest_dictionaries(&self, compression_level: i32) -> Result<HashMap<String, ChunkDictionary>, StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT class, id, data FROM dictionaries d
+             WHERE id = (SELECT MAX(id) FROM dictionaries WHERE class = d.class)"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?;
+        
+        let mut dicts = HashMap::new();
+        for row in rows {
+            let (class, id, data) = row?;
+            dicts.insert(class, ChunkDictionary::new(id, &data, compression_level));
+        }
+        Ok(dicts)
+    }
+    
+    /// 删除不再被任何块引用、也不是所属类别最新版本的字典
+    fn prune_dictionaries(&self) -> Result<usize, StoreError> {
+        let mut used: HashSet<i64> = HashSet::new();
+        self.chunks.for_each(&mut |entry| {
+            used.extend(entry.codec.dict_id());
+            Ok(())
+        })?;
+        
+        let unused: Vec<i64> = {
+            let mut stmt = self.conn.prepare(
+                "SELECT id FROM dictionaries
+                 WHERE id NOT IN (SELECT MAX(id) FROM dictionaries GROUP BY class)
+                   AND id NOT IN (SELECT dict_id FROM quarantined_chunks WHERE dict_id IS NOT NULL)"
+            )?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<Result<Vec<i64>, _>>()?
+                .into_iter()
+                .filter(|id| !used.contains(id))
+                .collect()
+        };
+        for id in &unused {
+            self.conn.execute("DELETE FROM dictionaries WHERE id = ?", [id])?;
+        }
+        Ok(unused.len())
+    }
+    
+    /// 重新编码一批块：旧版字典压缩的块总是改用新字典，其余块只在变小时改用
+    fn reencode_chunks(
+        &mut self,
+        hashes: &[u128],
+        dict: &ChunkDictionary,
+        report: &mut TrainReport,
+    ) -> Result<(), StoreError> {
+        let mut originals = Vec::with_capacity(hashes.len());
+        for &hash in hashes {
+            if let Some((compressed, original_size, codec)) = self.read_chunk_raw(hash)? {
+                let data = self.decompress(&compressed, original_size, codec)?;
+                originals.push((hash, compressed.len(), codec.dict_id().is_some(), data));
+            }
+        }
+        
+        // 字典自带压缩级别；字典压缩后不变小的块原样保存
+        let encoded = originals
+            .into_par_iter()
+            .map(|(hash, size, had_dict, data)| {
+                encode_chunk(&data, Some(dict), Codec::Zstd, 0).map(|encoded| (hash, size, had_dict, encoded))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        
+        let mut updates = Vec::new();
+        for (hash, size, had_dict, (compressed, codec)) in encoded {
+            report.size_before += size as u64;
+            if had_dict || compressed.len() < size {
+                report.size_after += compressed.len() as u64;
+                report.chunks_reencoded += 1;
+                updates.push((hash, compressed, codec));
+            } else {
+                report.size_after += size as u64;
+            }
+        }
+        self.chunks.replace(&updates)
+    }
+}
+
+impl ModArchive {
+    /// 按内容类别训练新字典，并用它重新编码该类别已有的块
+    pub fn train_dictionaries(&mut self, options: &TrainOptions) -> Result<Vec<TrainReport>, StoreError> {
+        // 按类别收集块（同一块只归入第一个遇到的类别）
+        let mut classes: Vec<(String, Vec<u128>)> = Vec::new();
+        let mut class_index: HashMap<String, usize> = HashMap::new();
+        let mut seen: HashSet<u128> = HashSet::new();
+        
+        let mut manifests = Vec::new();
+        for (id, _, _) in self.list_mods()? {
+            if let Some(manifest) = self.load_manifest(&id)? {
+                manifests.push(manifest);
+            }
+            manifests.extend(self.load_versions(&id)?.into_iter().map(|(_, m)| m));
+        }
+        for manifest in &manifests {
+            for file in &manifest.files {
+                let Some(class) = content_class(file) else {
+                    continue;
+                };
+                let index = *class_index.entry(class.clone()).or_insert_with(|| {
+                    classes.push((class, Vec::new()));
+                    classes.len() - 1
+                });
+                for hash_str in &file.chunks {
+                    let hash = parse_hash(hash_str)?;
+                    if seen.insert(hash) {
+                        classes[index].1.push(hash);
+                    }
+                }
+            }
+        }
+        
+        let mut reports = Vec::new();
+        for (class, hashes) in classes {
+            let mut report = TrainReport {
+                class: class.clone(),
+                ..Default::default()
+            };
+            if hashes.len() < MIN_SAMPLES {
+                report.note = Some(format!("only {} chunks, need at least {}", hashes.len(), MIN_SAMPLES));
+                reports.push(report);
+                continue;
+            }
+            
+            // 均匀抽样
+            let step = hashes.len().div_ceil(options.max_samples.max(1));
+            let sample_hashes: Vec<u128> = hashes.iter().step_by(step).copied().collect();
+            let samples = self.store.read_chunks(&sample_hashes)?;
+            report.samples = samples.len();
+            
+            let data = match zstd::dict::from_samples(&samples, options.max_dict_size) {
+                Ok(data) => data,
+                Err(e) => {
+                    report.note = Some(format!("training failed: {}", e));
+                    reports.push(report);
+                    continue;
+                }
+            };
+            
+            self.store.conn.execute(
+                "INSERT INTO dictionaries (class, data, created_at) VALUES (?, ?, ?)",
+                params![class, data, chrono_timestamp()],
+            )?;
+            let dict_id = self.store.conn.last_insert_rowid();
+            let dict = ChunkDictionary::new(dict_id, &data, self.config.compression_level);
+            report.dict_id = Some(dict_id);
+            report.dict_size = data.len();
+            
+            for batch in hashes.chunks(REENCODE_BATCH) {
+                self.store.reencode_chunks(batch, &dict, &mut report)?;
+            }
+            reports.push(report);
+        }
+        
+        self.store.prune_dictionaries()?;
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+    
+    /// 内容相似但互不相同的 BC7 纹理：单独压缩效果很差，字典可以捕获共同部分
+    fn textures(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 12345u32;
+        let template: Vec<u8> = (0..4096).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        
+        (0..count).map(|i| {
+            let mut data = vec![0u8; 148];
+            data[0..4].copy_from_slice(b"DDS ");
+            data[12..16].copy_from_slice(&64u32.to_le_bytes());
+            data[16..20].copy_from_slice(&64u32.to_le_bytes());
+            data[84..88].copy_from_slice(b"DX10");
+            data[128..132].copy_from_slice(&98u32.to_le_bytes());
+            let mut payload = template.clone();
+            for (j, byte) in payload.iter_mut().enumerate().step_by(97) {
+                *byte = (i as u32 * 31 + j as u32) as u8;
+            }
+            data.extend_from_slice(&payload);
+            data
+        }).collect()
+    }
+    
+    #[test]
+    fn test_train_and_reencode() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let files = textures(40);
+        for (i, data) in files.iter().enumerate() {
+            fs::write(src.path().join(format!("t{}.dds", i)), data).unwrap();
+        }
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        let before = archive.get_stats().unwrap().chunks.stored_size;
+        
+        let reports = archive.train_dictionaries(&TrainOptions {
+            max_dict_size: 8192,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].class, "dds:DXGI_98");
+        assert_eq!(reports[0].chunks_reencoded, 40);
+        assert!(reports[0].size_after < reports[0].size_before / 2, "{:?}", reports[0]);
+        assert!(archive.get_stats().unwrap().chunks.stored_size < before);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        for (i, data) in files.iter().enumerate() {
+            assert_eq!(&fs::read(out.path().join(format!("t{}.dds", i))).unwrap(), data);
+        }
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+        
+        // 之后归档的同类块直接使用字典；再次训练后旧字典不再被引用而被删除
+        let src2 = tempdir().unwrap();
+        fs::write(src2.path().join("new.dds"), &textures(41)[40]).unwrap();
+        archive.archive_mod(src2.path(), Some("n"), None).unwrap();
+        let with_dict: i64 = archive.store.conn
+            .query_row("SELECT COUNT(*) FROM chunks WHERE dict_id IS NOT NULL", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(with_dict, 41);
+        
+        archive.train_dictionaries(&TrainOptions { max_dict_size: 8192, ..Default::default() }).unwrap();
+        let dicts: i64 = archive.store.conn
+            .query_row("SELECT COUNT(*) FROM dictionaries", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(dicts, 1);
+    }
+}
@@ -0,0 +1,302 @@
+//! 存储一致性检查与修复
+//!
+//! 根据 `mods` 表中的所有清单重建预期的引用计数，与块后端中的引用计数比对，
+//! 并查找孤立的块、`compressed/*.zst` 文件和 `mods/<id>` 保留目录。
+
+use rusqlite::params;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{parse_hash, parse_manifest};
+use crate::store::{chrono_timestamp, ChunkStore, StoreError};
+
+/// 引用计数不一致的块
+#[derive(Debug, Clone)]
+pub struct RefMismatch {
+    pub hash: String,
+    pub expected: i64,
+    pub actual: i64,
+}
+
+/// 一致性检查结果
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub mods_checked: usize,
+    pub versions_checked: usize,
+    pub chunks_checked: usize,
+    /// 引用计数与清单不一致的块
+    pub ref_mismatches: Vec<RefMismatch>,
+    /// 没有任何清单引用、但 ref_count > 0 的块
+    pub orphaned_chunks: Vec<String>,
+    /// 没有清单引用的 `compressed/*.zst` 文件
+    pub orphaned_compressed: Vec<String>,
+    /// 没有对应 mod 的 `mods/<id>` 目录
+    pub orphaned_preserved: Vec<String>,
+    /// 没有对应历史版本的 `versions/<id>` 目录
+    pub orphaned_versions: Vec<String>,
+    /// 清单引用但不存在的块 (mod_id, hash)
+    pub missing_chunks: Vec<(String, String)>,
+    /// 无法解析的清单
+    pub corrupt_manifests: Vec<String>,
+    /// 是否已执行修复
+    pub repaired: bool,
+    /// 隔离目录（修复时孤立文件被移动到这里）
+    pub quarantine_path: Option<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.ref_mismatches.is_empty()
+            && self.orphaned_chunks.is_empty()
+            && self.orphaned_compressed.is_empty()
+            && self.orphaned_preserved.is_empty()
+            && self.orphaned_versions.is_empty()
+            && self.missing_chunks.is_empty()
+            && self.corrupt_manifests.is_empty()
+    }
+}
+
+impl ChunkStore {
+    /// 检查存储一致性，`repair` 为 true 时修正引用计数并隔离孤立数据
+    ///
+    /// 存在无法解析的清单时不会修复：那些清单的引用无法计入，
+    /// 修复会把它们仍在使用的块当成孤立数据。
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, StoreError> {
+        let mut report = FsckReport::default();
+        
+        // 从所有清单重建预期引用计数
+        let mut expected: HashMap<u128, i64> = HashMap::new();
+        let mut compressed_ids: HashSet<String> = HashSet::new();
+        let mut mod_ids: HashSet<String> = HashSet::new();
+        let mut versioned_ids: HashSet<String> = HashSet::new();
+        let mut chunk_owners: Vec<(String, u128)> = Vec::new();
+        
+        // 历史版本同样持有块引用
+        let manifests: Vec<(String, Option<i64>, String)> = {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, NULL, manifest FROM mods
+                 UNION ALL SELECT id, version, manifest FROM mod_versions"
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        
+        for (id, version, manifest_json) in manifests {
+            let id = match version {
+                Some(v) => {
+                    report.versions_checked += 1;
+                    versioned_ids.insert(id.clone());
+                    format!("{}@{}", id, v)
+                }
+                None => {
+                    report.mods_checked += 1;
+                    mod_ids.insert(id.clone());
+                    id
+                }
+            };
+            
+            let manifest = match parse_manifest(&id, &manifest_json) {
+                Ok(m) => m,
+                Err(_) => {
+                    report.corrupt_manifests.push(id);
+                    continue;
+                }
+            };
+            let hashes = match manifest.chunk_refs() {
+                Ok(h) => h,
+                Err(_) => {
+                    report.corrupt_manifests.push(id);
+                    continue;
+                }
+            };
+            
+            for hash in hashes {
+                let count = expected.entry(hash).or_insert(0);
+                if *count == 0 {
+                    chunk_owners.push((id.clone(), hash));
+                }
+                *count += 1;
+            }
+            compressed_ids.extend(manifest.compressed_ids().map(String::from));
+        }
+        
+        // 与块后端比对
+        let mut present: HashSet<u128> = HashSet::new();
+        self.chunks.for_each(&mut |entry| {
+            present.insert(entry.hash);
+            report.chunks_checked += 1;
+            
+            match expected.get(&entry.hash) {
+                Some(&count) if count != entry.ref_count => report.ref_mismatches.push(RefMismatch {
+                    hash: format!("{:032x}", entry.hash),
+                    expected: count,
+                    actual: entry.ref_count,
+                }),
+                None if entry.ref_count > 0 => report.orphaned_chunks.push(format!("{:032x}", entry.hash)),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        
+        for (id, hash) in chunk_owners {
+            if !present.contains(&hash) {
+                report.missing_chunks.push((id, format!("{:032x}", hash)));
+            }
+        }
+        
+        // 孤立的压缩文件与保留目录
+        let base = PathBuf::from(self.base_path());
+        for name in list_dir(&base.join("compressed"))? {
+            let referenced = name.strip_suffix(".zst")
+                .is_some_and(|id| compressed_ids.contains(id));
+            if !referenced {
+                report.orphaned_compressed.push(name);
+            }
+        }
+        for name in list_dir(&base.join("mods"))? {
+            if !mod_ids.contains(&name) {
+                report.orphaned_preserved.push(name);
+            }
+        }
+        for name in list_dir(&base.join("versions"))? {
+            if !versioned_ids.contains(&name) {
+                report.orphaned_versions.push(name);
+            }
+        }
+        
+        if repair && report.corrupt_manifests.is_empty() {
+            self.repair(&mut report)?;
+        }
+        
+        Ok(report)
+    }
+    
+    fn repair(&mut self, report: &mut FsckReport) -> Result<(), StoreError> {
+        let now = chrono_timestamp();
+        
+        // 隔离表总是保存块数据本身，与块后端无关
+        let orphaned_hashes: HashSet<u128> = report.orphaned_chunks.iter()
+            .map(|h| parse_hash(h))
+            .collect::<Result<_, _>>()?;
+        let mut entries = Vec::new();
+        self.chunks.for_each(&mut |entry| {
+            if orphaned_hashes.contains(&entry.hash) {
+                entries.push(entry.clone());
+            }
+            Ok(())
+        })?;
+        let mut orphaned = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if let Some((data, _, _)) = self.read_chunk_raw(entry.hash)? {
+                orphaned.push((entry, data));
+            }
+        }
+        
+        let tx = self.conn.unchecked_transaction()?;
+        for mismatch in &report.ref_mismatches {
+            self.chunks.set_ref(parse_hash(&mismatch.hash)?, mismatch.expected)?;
+        }
+        
+        // 孤立块移入隔离表，而不是直接删除
+        {
+            let mut stmt_quarantine = tx.prepare_cached(
+                "INSERT OR REPLACE INTO quarantined_chunks (hash, data, original_size, ref_count, dict_id, codec, quarantined_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)"
+            )?;
+            for (entry, data) in &orphaned {
+                stmt_quarantine.execute(params![
+                    &entry.hash.to_le_bytes()[..],
+                    data,
+                    entry.original_size as i64,
+                    entry.ref_count,
+                    entry.codec.dict_id(),
+                    entry.codec.tag(),
+                    now
+                ])?;
+            }
+        }
+        let removed: Vec<u128> = orphaned.iter().map(|(entry, _)| entry.hash).collect();
+        self.chunks.remove(&removed)?;
+        tx.commit()?;
+        
+        if !report.orphaned_compressed.is_empty()
+            || !report.orphaned_preserved.is_empty()
+            || !report.orphaned_versions.is_empty()
+        {
+            let base = PathBuf::from(self.base_path());
+            let quarantine = base.join("quarantine").join(now.to_string());
+            
+            for name in &report.orphaned_compressed {
+                move_into(&base.join("compressed").join(name), &quarantine.join("compressed").join(name))?;
+            }
+            for name in &report.orphaned_preserved {
+                move_into(&base.join("mods").join(name), &quarantine.join("mods").join(name))?;
+            }
+            for name in &report.orphaned_versions {
+                move_into(&base.join("versions").join(name), &quarantine.join("versions").join(name))?;
+            }
+            report.quarantine_path = Some(quarantine);
+        }
+        
+        report.repaired = true;
+        Ok(())
+    }
+}
+
+/// 列出目录中的条目名（目录不存在时为空）
+fn list_dir(dir: &Path) -> Result<Vec<String>, StoreError> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|e| Ok(e?.file_name().to_string_lossy().to_string()))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn move_into(src: &Path, dst: &Path) -> Result<(), StoreError> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(src, dst)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ModArchive;
+    use std::fs;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_fsck_detects_and_repairs_drift() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(src.path().join("a.dat"), &data).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        // 模拟中断后的引用漂移和遗留文件
+        let conn = rusqlite::Connection::open(store.path().join("store.db")).unwrap();
+        conn.execute("UPDATE chunks SET ref_count = ref_count + 1", []).unwrap();
+        conn.execute(
+            "INSERT INTO chunks (hash, data, original_size, ref_count) VALUES (?, ?, 1, 1)",
+            rusqlite::params![&7u128.to_le_bytes()[..], &[0u8][..]],
+        ).unwrap();
+        fs::create_dir_all(store.path().join("mods").join("gone")).unwrap();
+        
+        let report = archive.fsck(true).unwrap();
+        assert_eq!(report.ref_mismatches.len(), 3);
+        assert_eq!(report.orphaned_chunks.len(), 1);
+        assert_eq!(report.orphaned_preserved, vec!["gone".to_string()]);
+        assert!(report.repaired);
+        assert!(!store.path().join("mods").join("gone").exists());
+        
+        assert!(archive.fsck(false).unwrap().is_clean());
+    }
+}
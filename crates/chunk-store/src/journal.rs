@@ -0,0 +1,231 @@
+//! 文件操作日志
+//!
+//! 归档时所有新文件先写入 `staging/<token>/`，数据库事务提交时把要执行的文件操作
+//! 记录到 `pending_ops` 表，然后再把文件移动到位。
+//!
+//! - 事务提交前崩溃：`pending_ops` 中没有记录，下次打开时删除暂存目录（回滚）
+//! - 事务提交后崩溃：下次打开时重新执行记录的操作（前滚）
+//!
+//! 归档过程中分批写入的块引用登记在 `pending_refs` 中，与清单在同一事务中确认；
+//! 打开时仍有登记说明归档未提交，这些引用被撤销。
+//!
+//! 所有操作都是幂等的，可以重复执行。恢复会清理所有未提交的暂存区，
+//! 因此只在没有其他进程打开存储时执行：每个打开的存储持有 `store.lock` 的共享锁，
+//! 取得排他锁才恢复。同一存储同一时间仍只能由一个进程写入。
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::store::{chrono_timestamp, clear_pending_op, ChunkStore, StoreError};
+
+static TOKEN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 文件操作，路径相对于存储根目录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum FileOp {
+    /// 移动文件或目录（源不存在或目标已存在时跳过）
+    Move { from: PathBuf, to: PathBuf },
+    /// 删除文件或目录（不存在时跳过）
+    Remove { path: PathBuf },
+}
+
+impl FileOp {
+    fn apply(&self, base: &Path) -> io::Result<()> {
+        match self {
+            FileOp::Move { from, to } => {
+                let (from, to) = (base.join(from), base.join(to));
+                if !from.exists() || to.exists() {
+                    return Ok(());
+                }
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(from, to)
+            }
+            FileOp::Remove { path } => remove_path(&base.join(path)),
+        }
+    }
+}
+
+/// 一次归档或删除的暂存区
+pub(crate) struct Staging {
+    token: String,
+    base: PathBuf,
+    ops: Vec<FileOp>,
+}
+
+impl Staging {
+    /// 在 `staging/` 下创建新的暂存目录
+    pub fn create(base: &Path) -> io::Result<Self> {
+        let token = format!(
+            "{}-{}-{}",
+            chrono_timestamp(),
+            std::process::id(),
+            TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let staging = Self {
+            token,
+            base: base.to_path_buf(),
+            ops: Vec::new(),
+        };
+        fs::create_dir_all(staging.base.join(staging.dir()))?;
+        Ok(staging)
+    }
+    
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+    
+    /// 暂存目录（相对路径）
+    pub fn dir(&self) -> PathBuf {
+        Path::new("staging").join(&self.token)
+    }
+    
+    /// 暂存目录中某个相对路径对应的绝对路径
+    pub fn path(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.base.join(self.dir()).join(relative)
+    }
+    
+    pub fn push(&mut self, op: FileOp) {
+        self.ops.push(op);
+    }
+    
+    /// 序列化的操作计划，随数据库事务一起提交
+    pub fn plan(&self) -> Result<String, StoreError> {
+        serde_json::to_string(&self.ops).map_err(|e| StoreError::CorruptJournal(e.to_string()))
+    }
+    
+    /// 事务提交后执行文件操作并清理暂存区
+    pub fn finish(self, conn: &Connection) -> Result<(), StoreError> {
+        for op in &self.ops {
+            op.apply(&self.base)?;
+        }
+        // 先删除日志记录再删除暂存目录，避免重放时把已就位的文件当成待移动文件
+        clear_pending_op(conn, &self.token)?;
+        remove_path(&self.base.join(self.dir()))?;
+        Ok(())
+    }
+    
+    /// 事务已提交时执行文件操作：失败时日志记录仍在，下次打开存储时由 `recover` 前滚，
+    /// 不把已生效的提交报告为失败
+    pub fn finish_committed(self, conn: &Connection) {
+        let _ = self.finish(conn);
+    }
+    
+    /// 放弃暂存的文件（事务未提交）
+    pub fn discard(self) {
+        let _ = remove_path(&self.base.join(self.dir()));
+    }
+}
+
+/// 打开存储时恢复未完成的操作，返回处理的暂存区数量
+pub(crate) fn recover(store: &mut ChunkStore) -> Result<usize, StoreError> {
+    let base = PathBuf::from(store.base_path());
+    let pending = store.list_pending_ops()?;
+    let mut recovered = 0;
+    
+    // 已提交：前滚
+    for (token, plan) in &pending {
+        let ops: Vec<FileOp> = serde_json::from_str(plan)
+            .map_err(|e| StoreError::CorruptJournal(format!("{}: {}", token, e)))?;
+        for op in &ops {
+            op.apply(&base)?;
+        }
+        clear_pending_op(&store.conn, token)?;
+        remove_path(&base.join("staging").join(token))?;
+        recovered += 1;
+    }
+    
+    // 未提交：回滚
+    store.rollback_pending(None)?;
+    let staging_dir = base.join("staging");
+    if staging_dir.exists() {
+        for entry in fs::read_dir(&staging_dir)? {
+            remove_path(&entry?.path())?;
+            recovered += 1;
+        }
+    }
+    
+    Ok(recovered)
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Codec, PreparedChunk};
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_recover_rolls_back_and_forward() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        
+        // 未提交的暂存区被删除
+        let uncommitted = Staging::create(dir.path()).unwrap();
+        fs::write(uncommitted.path("junk"), b"x").unwrap();
+        
+        // 已提交但文件尚未移动的暂存区被前滚
+        let mut committed = Staging::create(dir.path()).unwrap();
+        fs::write(committed.path("file"), b"data").unwrap();
+        committed.push(FileOp::Move {
+            from: committed.dir().join("file"),
+            to: PathBuf::from("mods/m/file"),
+        });
+        store.conn.execute(
+            "INSERT INTO pending_ops (token, plan, created_at) VALUES (?, ?, 0)",
+            [committed.token(), &committed.plan().unwrap()],
+        ).unwrap();
+        drop(store);
+        
+        ChunkStore::open(dir.path()).unwrap();
+        assert_eq!(fs::read(dir.path().join("mods/m/file")).unwrap(), b"data");
+        assert_eq!(fs::read_dir(dir.path().join("staging")).unwrap().count(), 0);
+    }
+    
+    #[test]
+    fn test_open_during_uncommitted_archive() {
+        let dir = tempdir().unwrap();
+        let mut store = ChunkStore::open(dir.path()).unwrap();
+        
+        // 归档进行中：块引用已登记，保留文件已暂存
+        let staging = Staging::create(dir.path()).unwrap();
+        fs::write(staging.path("mod.ini"), b"[mod]").unwrap();
+        let chunk = PreparedChunk {
+            hash: 1,
+            compressed: b"data".to_vec(),
+            original_size: 4,
+            codec: Codec::Raw,
+        };
+        store.put_pending(staging.token(), &[chunk]).unwrap();
+        
+        // 另一个进程打开存储不会撤销进行中的归档
+        let other = ChunkStore::open(dir.path()).unwrap();
+        assert!(staging.path("mod.ini").exists());
+        let pending: i64 = other.conn
+            .query_row("SELECT COUNT(*) FROM pending_refs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pending, 1);
+        drop(other);
+        
+        // 所有打开的存储关闭后才恢复
+        drop(store);
+        ChunkStore::open(dir.path()).unwrap();
+        assert_eq!(fs::read_dir(dir.path().join("staging")).unwrap().count(), 0);
+    }
+}
@@ -10,23 +10,69 @@
 //! - 4KB 块：69% 去重率，每文件约 1600 块
 //! - 与文件系统块大小对齐，IO 效率高
 //!
+//! 不同的 mod 库结果可能不同：`simulate` 按候选配置重放存储中的数据，
+//! 报告去重率、索引大小和解码吞吐量，可以据此调整 `ChunkConfig`。
+//!
+//! 固定分块在数据插入或删除少量字节后，之后的所有块边界都会偏移。
+//! `ChunkingStrategy::ContentDefined` 用 FastCDC 按内容确定边界，
+//! 适合偏移重新导出的缓冲区；每个文件的策略记录在清单中，两种分块可以共存。
+//!
+//! 默认每个块是 `chunks` 表中的一行 BLOB。`BackendKind::Pack` 把块追加到大的包文件，
+//! SQLite 只保存索引，适合块数很多的存储；两种布局可以用 `migrate_backend` 互相转换。
+//! 块的保存方式由 `ChunkBackend` 抽象，另有内存后端（测试用）和每块一个文件的目录后端。
+//! 提取时可以选择 `ExtractMode::Hardlink` 或 `Reflink`：每种文件内容只在 `extract-cache/` 中还原一次，
+//! 多个 mod 的输出链接到同一份缓存。
+//!
 //! ### 为什么用 zstd 而不是 gzip？
 //!
 //! - zstd 压缩速度比 gzip 快 3-5x
 //! - 压缩率相当或更好
 //! - 支持字典压缩，对小块更友好
 //!
+//! 每个块记录自己的编码（`Codec`）：压缩后不变小的块（例如 BC7 纹理）原样保存，
+//! 需要更快解压时可以改用 lz4。压缩级别和分块策略保存在存储中，
+//! 已有的块可以用 `recompress` 按新级别重新编码，或用 `rechunk` 按新策略重新分块。
+//!
 //! ### 为什么用 xxh3 而不是 md5？
 //!
 //! - xxh3 比 md5 快 10x
 //! - 128 位输出，碰撞概率足够低
 //! - 专为数据去重设计
+//!
+//! 块来自不可信来源时可以改用 BLAKE3（截取 128 位），防止构造的碰撞覆盖已有的块。
+//! 算法记录在存储和每个清单中，算法不一致的清单会被拒绝；`rehash` 校验后把整个存储迁移到新算法。
 
+mod backend;
 mod chunk;
 mod store;
 mod archive;
 mod dds;
+mod buffer;
+mod verify;
+mod fsck;
+mod journal;
+mod schema;
+mod dict;
+mod stats;
+mod pack;
+mod recompress;
+mod rehash;
+mod analyze;
+mod simulate;
 
-pub use chunk::{ChunkConfig, chunk_data, hash_chunk};
-pub use store::{ChunkStore, StoreStats};
-pub use archive::{ModArchive, ModManifest};
+pub use chunk::{ChunkConfig, ChunkingStrategy, Codec, HashAlgorithm, chunk_data, hash_chunk, decompress_chunk};
+pub use store::{BackendKind, ChunkStore, StoreError, RawChunk};
+pub use backend::{ChunkBackend, ChunkEntry, SqliteBackend, MemoryBackend, DirectoryBackend};
+pub use archive::{
+    ModArchive, ModManifest, FileManifest, ArchiveOptions, OnExisting,
+    ArchiveReport, ArchivedFile, ArchiveChanges, FileHandling, ExtractMode, DEFAULT_MEMORY_LIMIT,
+};
+pub use verify::VerifyReport;
+pub use fsck::{FsckReport, RefMismatch};
+pub use stats::{StoreStats, ClassStats, CodecStats, ModUsage};
+pub use dict::{TrainOptions, TrainReport};
+pub use recompress::{RecompressReport, RechunkReport};
+pub use rehash::RehashReport;
+pub use analyze::{AnalyzeReport, ModOverlap};
+pub use simulate::SimulationResult;
+pub use schema::{FORMAT_VERSION, HASH_ALGORITHM};
@@ -0,0 +1,304 @@
+//! 包文件（pack）块后端
+//!
+//! 数百万个 4KB 的 BLOB 行会让 `store.db` 非常大，`VACUUM` 很慢，提取时读取也不连续。
+//! pack 后端把压缩后的块顺序追加到 `packs/<id>.pack`，`chunks` 表只保存
+//! `(pack_id, pack_offset, pack_len)` 索引，`data` 列为空。
+//!
+//! `packs.size` 记录已提交的长度，与块索引在同一事务中更新；
+//! 事务回滚后文件末尾多出的字节在下次追加前被截断。
+//! 删除块只删除索引，包内失效字节由 `gc` 压缩回收：存活数据不足一半的包
+//! 被重写到新包中，旧文件经日志在事务提交后删除。
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::journal::{FileOp, Staging};
+use crate::store::{chrono_timestamp, insert_pending_op, StoreError};
+
+/// 单个包文件达到该大小后开始写入新包
+pub(crate) const PACK_TARGET_SIZE: u64 = 256 * 1024 * 1024;
+
+/// 存活数据低于该比例的包在 `gc` 时被重写
+const COMPACT_THRESHOLD: f64 = 0.5;
+
+/// 包文件相对存储根目录的路径
+pub(crate) fn pack_path(id: i64) -> PathBuf {
+    Path::new("packs").join(format!("{:08}.pack", id))
+}
+
+/// 包文件目录与已打开的读句柄
+pub(crate) struct PackStore {
+    base: PathBuf,
+    readers: RefCell<HashMap<i64, File>>,
+}
+
+impl PackStore {
+    pub fn new(base: &Path) -> Self {
+        Self {
+            base: base.to_path_buf(),
+            readers: RefCell::new(HashMap::new()),
+        }
+    }
+    
+    /// 读取包中的一段数据
+    pub fn read(&self, pack_id: i64, offset: u64, len: usize) -> Result<Vec<u8>, StoreError> {
+        let mut readers = self.readers.borrow_mut();
+        let file = match readers.entry(pack_id) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(File::open(self.base.join(pack_path(pack_id)))?)
+            }
+        };
+        
+        let mut data = vec![0u8; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+    
+    /// 关闭读句柄（删除包文件之前调用，Windows 上无法删除已打开的文件）
+    pub fn close_readers(&self) {
+        self.readers.borrow_mut().clear();
+    }
+    
+    /// 在事务中追加块的写入器
+    pub fn writer<'a>(&self, conn: &'a Connection) -> PackWriter<'a> {
+        PackWriter {
+            conn,
+            base: self.base.clone(),
+            current: None,
+            touched: Vec::new(),
+            fresh: false,
+        }
+    }
+}
+
+/// 正在写入的包
+struct OpenPack {
+    id: i64,
+    file: File,
+    size: u64,
+}
+
+/// 包写入器，`finish` 后事务才能提交
+pub(crate) struct PackWriter<'a> {
+    conn: &'a Connection,
+    base: PathBuf,
+    current: Option<OpenPack>,
+    /// 写入过的包 (id, 大小)
+    touched: Vec<(i64, u64)>,
+    /// 不追加到已有的包（压缩时使用）
+    fresh: bool,
+}
+
+impl PackWriter<'_> {
+    /// 追加一个块，返回 (pack_id, offset)
+    pub fn append(&mut self, data: &[u8]) -> Result<(i64, u64), StoreError> {
+        let full = self.current.as_ref()
+            .is_some_and(|p| p.size > 0 && p.size + data.len() as u64 > PACK_TARGET_SIZE);
+        if full {
+            self.close_current()?;
+        }
+        if self.current.is_none() {
+            self.current = Some(self.open_pack()?);
+        }
+        
+        let pack = self.current.as_mut().expect("pack opened above");
+        let offset = pack.size;
+        pack.file.write_all(data)?;
+        pack.size += data.len() as u64;
+        Ok((pack.id, offset))
+    }
+    
+    /// 同步写入的包并在事务中记录它们的大小
+    pub fn finish(mut self) -> Result<(), StoreError> {
+        self.close_current()?;
+        let mut stmt = self.conn.prepare_cached("UPDATE packs SET size = ? WHERE id = ?")?;
+        for (id, size) in &self.touched {
+            stmt.execute(params![*size as i64, id])?;
+        }
+        Ok(())
+    }
+    
+    fn close_current(&mut self) -> Result<(), StoreError> {
+        if let Some(pack) = self.current.take() {
+            pack.file.sync_data()?;
+            self.touched.push((pack.id, pack.size));
+        }
+        Ok(())
+    }
+    
+    /// 打开最新的未满的包，或创建新包
+    fn open_pack(&mut self) -> Result<OpenPack, StoreError> {
+        let latest: Option<(i64, i64)> = if self.fresh || !self.touched.is_empty() {
+            None
+        } else {
+            self.conn.query_row(
+                "SELECT id, size FROM packs ORDER BY id DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).optional()?
+        };
+        
+        let (id, size) = match latest {
+            Some((id, size)) if (size as u64) < PACK_TARGET_SIZE => (id, size as u64),
+            _ => {
+                self.conn.execute(
+                    "INSERT INTO packs (size, created_at) VALUES (0, ?)",
+                    [chrono_timestamp()],
+                )?;
+                (self.conn.last_insert_rowid(), 0)
+            }
+        };
+        
+        let path = self.base.join(pack_path(id));
+        fs::create_dir_all(path.parent().expect("pack path has a parent"))?;
+        let mut file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+        // 截断未提交的写入
+        file.set_len(size)?;
+        file.seek(SeekFrom::Start(size))?;
+        Ok(OpenPack { id, file, size })
+    }
+}
+
+/// 重写存活数据不足一半的包并删除没有存活数据的包，返回回收的字节数
+pub(crate) fn compact(conn: &Connection, packs: &PackStore) -> Result<u64, StoreError> {
+    let candidates: Vec<(i64, u64, u64)> = {
+        let mut stmt = conn.prepare(
+            "SELECT p.id, p.size, COALESCE(SUM(c.pack_len), 0) FROM packs p
+             LEFT JOIN chunks c ON c.pack_id = p.id
+             GROUP BY p.id ORDER BY p.id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64))
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|&(_, size, live)| (live as f64) < size as f64 * COMPACT_THRESHOLD || live == 0)
+            .collect()
+    };
+    if candidates.is_empty() {
+        return Ok(0);
+    }
+    
+    let mut staging = Staging::create(&packs.base)?;
+    let mut reclaimed = 0;
+    
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut writer = packs.writer(&tx);
+        writer.fresh = true;
+        let mut stmt_live = tx.prepare_cached(
+            "SELECT hash, pack_offset, pack_len FROM chunks WHERE pack_id = ? ORDER BY pack_offset"
+        )?;
+        let mut stmt_move = tx.prepare_cached(
+            "UPDATE chunks SET pack_id = ?, pack_offset = ? WHERE hash = ?"
+        )?;
+        
+        for &(id, size, live) in &candidates {
+            let rows: Vec<(Vec<u8>, i64, i64)> = stmt_live
+                .query_map([id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?;
+            for (hash, offset, len) in rows {
+                let data = packs.read(id, offset as u64, len as usize)?;
+                let (new_id, new_offset) = writer.append(&data)?;
+                stmt_move.execute(params![new_id, new_offset as i64, hash])?;
+            }
+            
+            tx.execute("DELETE FROM packs WHERE id = ?", [id])?;
+            staging.push(FileOp::Remove { path: pack_path(id) });
+            reclaimed += size - live;
+        }
+        writer.finish()?;
+    }
+    insert_pending_op(&tx, (staging.token(), &staging.plan()?))?;
+    tx.commit()?;
+    
+    packs.close_readers();
+    staging.finish_committed(conn);
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BackendKind, ModArchive};
+    use std::fs;
+    use tempfile::tempdir;
+    
+    fn pack_files(dir: &std::path::Path) -> usize {
+        fs::read_dir(dir.join("packs")).map_or(0, |entries| entries.count())
+    }
+    
+    #[test]
+    fn test_pack_roundtrip_and_compaction() {
+        let a = tempdir().unwrap();
+        let b = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let big: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let small: Vec<u8> = (0..8192u32).map(|i| (i % 251) as u8).collect();
+        fs::write(a.path().join("big.dat"), &big).unwrap();
+        fs::write(b.path().join("small.dat"), &small).unwrap();
+        
+        let mut archive = ModArchive::create(store.path(), BackendKind::Pack).unwrap();
+        archive.archive_mod(a.path(), Some("a"), None).unwrap();
+        archive.archive_mod(b.path(), Some("b"), None).unwrap();
+        
+        let blob_bytes: i64 = archive.store.conn
+            .query_row("SELECT SUM(LENGTH(data)) FROM chunks", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(blob_bytes, 0);
+        assert_eq!(pack_files(store.path()), 1);
+        
+        // 删除大 mod 后包中大部分数据失效，gc 把存活的块重写到新包
+        archive.remove_mod("a").unwrap();
+        let (deleted, freed) = archive.gc().unwrap();
+        assert!(deleted > 0);
+        assert!(freed > 0);
+        assert_eq!(pack_files(store.path()), 1);
+        let pack_id: i64 = archive.store.conn
+            .query_row("SELECT id FROM packs", [], |r| r.get(0))
+            .unwrap();
+        assert!(pack_id > 1);
+        
+        archive.extract_mod("b", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("small.dat")).unwrap(), small);
+        assert!(archive.verify_mod("b").unwrap().is_ok());
+        assert!(archive.fsck(false).unwrap().is_clean());
+    }
+    
+    #[test]
+    fn test_migrate_backend() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(src.path().join("file.dat"), &data).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        let moved = archive.migrate_backend(BackendKind::Pack).unwrap();
+        assert!(moved > 0);
+        assert_eq!(archive.backend_name(), "pack");
+        assert_eq!(pack_files(store.path()), 1);
+        drop(archive);
+        
+        // 已有的存储按记录的后端打开
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        assert!(ModArchive::create(store.path(), BackendKind::Sqlite).is_err());
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("file.dat")).unwrap(), data);
+        
+        // 迁回 SQLite 后包文件被删除
+        archive.migrate_backend(BackendKind::Sqlite).unwrap();
+        assert_eq!(pack_files(store.path()), 0);
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+    }
+}
@@ -0,0 +1,400 @@
+//! 重新压缩与重新分块
+//!
+//! `recompress` 以新的压缩级别重新编码已有的块：块的 hash 和内容不变，只替换存储的数据。
+//! 每批块与进度（`meta.recompress_progress`）在同一事务中提交，中断后以相同参数再次执行会继续。
+//!
+//! `rechunk` 按新的分块策略重写每个 mod 的当前清单：文件由旧块还原后重新分块，
+//! 新块登记为未提交，与新清单在同一事务中替换旧清单，旧块随后由 `gc` 回收。
+//! 两者执行期间每个 mod 始终可以提取。
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{parse_hash, size_groups, ExtractPlan, FileChunker, FileManifest, ModArchive, ModManifest};
+use crate::buffer::load_strides;
+use crate::chunk::{decode_chunk, encode_chunk, ChunkDictionary, ChunkingStrategy, Codec};
+use crate::journal::Staging;
+use crate::schema;
+use crate::store::{CachedFile, ChunkStore, ModCommit, StoreError};
+
+/// 每批重新编码的块数
+const RECOMPRESS_BATCH: usize = 1024;
+
+/// 重新压缩的进度：`<编码>:<级别>:<最后完成的 hash>`
+const PROGRESS_KEY: &str = "recompress_progress";
+
+/// 重新压缩结果
+#[derive(Debug, Default)]
+pub struct RecompressReport {
+    /// 存储中的块数
+    pub chunks: usize,
+    /// 本次重新编码的块数（继续中断的任务时不含之前完成的部分）
+    pub chunks_reencoded: usize,
+    /// 是否继续了上次中断的任务
+    pub resumed: bool,
+    /// 本次重新编码的块编码前后的存储大小
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// 重新分块结果
+#[derive(Debug, Default)]
+pub struct RechunkReport {
+    pub mods_rewritten: usize,
+    /// 所有文件已经使用新策略而跳过的 mod
+    pub mods_skipped: usize,
+    pub files_rechunked: usize,
+    /// 重写前与回收旧块后所有块的存储大小
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// 文件是否已按该策略分块（旧版压缩文件不分块）
+fn uses_strategy(file: &FileManifest, strategy: ChunkingStrategy) -> bool {
+    match file.chunking {
+        _ if file.is_compressed() => true,
+        Some(ChunkingStrategy::MipAligned { size } | ChunkingStrategy::Strided { size, .. }) => {
+            size == strategy.chunk_size()
+        }
+        chunking => chunking == Some(strategy),
+    }
+}
+
+impl ChunkStore {
+    /// 以字典 id 对应的字典按 `compression_level` 创建编码字典
+    fn encoder_dictionary(&self, id: i64, compression_level: i32) -> Result<ChunkDictionary, StoreError> {
+        let data: Vec<u8> = self.conn
+            .query_row("SELECT data FROM dictionaries WHERE id = ?", [id], |row| row.get(0))
+            .map_err(|_| StoreError::DictionaryNotFound(id.to_string()))?;
+        Ok(ChunkDictionary::new(id, &data, compression_level))
+    }
+    
+    /// 重新编码一批块，并在同一事务中记录进度
+    fn recompress_chunks(
+        &mut self,
+        hashes: &[u128],
+        codec: Codec,
+        level: i32,
+        dictionaries: &mut HashMap<i64, ChunkDictionary>,
+        progress: &str,
+        report: &mut RecompressReport,
+    ) -> Result<(), StoreError> {
+        let mut raw = self.chunks.get_many(hashes)?;
+        let mut chunks = Vec::with_capacity(raw.len());
+        for hash in hashes {
+            let Some((data, original_size, old)) = raw.remove(hash) else {
+                continue;
+            };
+            let decoder = old.dict_id().map(|id| self.decoder_dictionary(id)).transpose()?;
+            if let Some(id) = old.dict_id() {
+                if let std::collections::hash_map::Entry::Vacant(e) = dictionaries.entry(id) {
+                    e.insert(self.encoder_dictionary(id, level)?);
+                }
+            }
+            chunks.push((*hash, data, original_size, old, decoder));
+        }
+        
+        // 字典压缩的块沿用原来的字典
+        let dictionaries = &*dictionaries;
+        let encoded = chunks
+            .into_par_iter()
+            .map(|(hash, data, original_size, old, decoder)| {
+                let original = decode_chunk(&data, original_size, old, decoder.as_deref())?;
+                let (dict, target) = match old.dict_id() {
+                    Some(id) => (Some(&dictionaries[&id]), Codec::Zstd),
+                    None => (None, codec),
+                };
+                encode_chunk(&original, dict, target, level).map(|encoded| (hash, data.len(), encoded))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        
+        let mut updates = Vec::with_capacity(encoded.len());
+        for (hash, size, (compressed, codec)) in encoded {
+            report.size_before += size as u64;
+            report.size_after += compressed.len() as u64;
+            updates.push((hash, compressed, codec));
+        }
+        report.chunks_reencoded += updates.len();
+        
+        let tx = self.conn.unchecked_transaction()?;
+        self.chunks.replace(&updates)?;
+        schema::set_meta(&tx, PROGRESS_KEY, progress)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl ModArchive {
+    /// 以 zstd 级别 `level` 重新压缩所有块，编码为当前配置的 `codec`
+    ///
+    /// 新级别立即保存为存储配置，之后写入的块直接使用。已有的块按 hash 顺序分批重新编码，
+    /// 以相同的编码和级别再次调用时从上次提交的批次继续。包文件中被替换的数据由 `gc` 回收。
+    pub fn recompress(&mut self, level: i32) -> Result<RecompressReport, StoreError> {
+        if !(1..=*zstd::compression_level_range().end()).contains(&level) {
+            return Err(StoreError::InvalidConfig(format!("invalid compression level: {}", level)));
+        }
+        self.config.compression_level = level;
+        self.store.save_chunk_config(&self.config)?;
+        
+        let target = format!("{}:{}", self.config.codec.name(), level);
+        let mut report = RecompressReport::default();
+        let mut cursor = None;
+        if let Some(progress) = self.store.meta(PROGRESS_KEY)? {
+            if let Some((previous, last)) = progress.rsplit_once(':') {
+                if previous == target {
+                    cursor = Some(parse_hash(last)?);
+                    report.resumed = true;
+                }
+            }
+        }
+        
+        let mut hashes = Vec::new();
+        self.store.chunks.for_each(&mut |entry| {
+            hashes.push(entry.hash);
+            Ok(())
+        })?;
+        hashes.sort_unstable();
+        report.chunks = hashes.len();
+        if let Some(cursor) = cursor {
+            hashes.retain(|&hash| hash > cursor);
+        }
+        
+        let mut dictionaries = HashMap::new();
+        for batch in hashes.chunks(RECOMPRESS_BATCH) {
+            let progress = format!("{}:{:032x}", target, batch[batch.len() - 1]);
+            self.store.recompress_chunks(batch, self.config.codec, level, &mut dictionaries, &progress, &mut report)?;
+        }
+        
+        self.store.conn.execute("DELETE FROM meta WHERE key = ?", [PROGRESS_KEY])?;
+        Ok(report)
+    }
+    
+    /// 按 `strategy` 重新分块所有 mod 的当前清单，最后回收不再被引用的旧块
+    ///
+    /// 新策略立即保存为存储配置。每个 mod 单独提交，中断后再次执行会跳过已经使用新策略的 mod；
+    /// 历史版本保留原来的块。
+    pub fn rechunk(&mut self, strategy: ChunkingStrategy) -> Result<RechunkReport, StoreError> {
+        strategy.validate()?;
+        self.config.strategy = strategy;
+        self.store.save_chunk_config(&self.config)?;
+        
+        let mut report = RechunkReport {
+            size_before: self.get_stats()?.chunks.stored_size,
+            ..Default::default()
+        };
+        let chunker = self.chunker()?;
+        for (id, _, _) in self.list_mods()? {
+            let Some(manifest) = self.load_manifest(&id)? else {
+                continue;
+            };
+            if manifest.files.iter().all(|file| uses_strategy(file, strategy)) {
+                report.mods_skipped += 1;
+                continue;
+            }
+            report.files_rechunked += self.rechunk_mod(&manifest, &chunker)?;
+            report.mods_rewritten += 1;
+        }
+        
+        self.gc()?;
+        report.size_after = self.get_stats()?.chunks.stored_size;
+        Ok(report)
+    }
+    
+    /// 重新分块单个 mod，返回重新分块的文件数；失败时撤销已写入的块引用
+    fn rechunk_mod(&mut self, manifest: &ModManifest, chunker: &FileChunker) -> Result<usize, StoreError> {
+        let staging = Staging::create(Path::new(self.store.base_path()))?;
+        let result = self.rechunk_files(manifest, chunker, staging.token())
+            .and_then(|(files, stored_size)| {
+                let rechunked = files.iter().filter(|f| !f.is_compressed()).count();
+                let rewritten = ModManifest {
+                    id: manifest.id.clone(),
+                    name: manifest.name.clone(),
+                    source_path: manifest.source_path.clone(),
+                    hash_algorithm: manifest.hash_algorithm,
+                    files,
+                    preserved_files: manifest.preserved_files.clone(),
+                    empty_dirs: manifest.empty_dirs.clone(),
+                    original_size: manifest.original_size,
+                    stored_size,
+                    created_at: manifest.created_at,
+                };
+                
+                // 文件内容不变，缓存记录原样保留
+                let released = manifest.chunk_refs()?;
+                let cache: Vec<CachedFile> = self.store.load_file_cache(&manifest.id)?.into_values().collect();
+                let plan = staging.plan()?;
+                let commit = ModCommit {
+                    id: &manifest.id,
+                    name: &manifest.name,
+                    reused: &[],
+                    released: &released,
+                    keep_version: None,
+                    journal: (staging.token(), &plan),
+                    cache: &cache,
+                    created_at: manifest.created_at,
+                };
+                let json = serde_json::to_string(&rewritten)
+                    .map_err(|e| StoreError::CorruptManifest(e.to_string()))?;
+                self.store.commit_mod(&commit, &json)?;
+                Ok(rechunked)
+            });
+        
+        match result {
+            Ok(rechunked) => {
+                staging.finish_committed(&self.store.conn);
+                Ok(rechunked)
+            }
+            Err(e) => {
+                let token = staging.token().to_string();
+                staging.discard();
+                let _ = self.store.rollback_pending(Some(&token));
+                Err(e)
+            }
+        }
+    }
+    
+    /// 按内存上限分组还原并重新分块文件，新块登记在 `token` 下，返回新的文件条目和新写入的字节数
+    fn rechunk_files(
+        &mut self,
+        manifest: &ModManifest,
+        chunker: &FileChunker,
+        token: &str,
+    ) -> Result<(Vec<FileManifest>, u64), StoreError> {
+        let mods_dir = PathBuf::from(self.store.base_path()).join("mods").join(&manifest.id);
+        let strides = load_strides(&mods_dir, &manifest.preserved_files);
+        let plans: Vec<ExtractPlan> = manifest.files.iter()
+            .map(|file| self.extract_plan(manifest, file))
+            .collect::<Result<_, _>>()?;
+        
+        let budget = (self.memory_limit / 4).max(1) as u64;
+        let mut files = Vec::with_capacity(plans.len());
+        let mut stored_size = 0;
+        for range in size_groups(&plans, budget) {
+            let needed: Vec<u128> = plans[range.clone()].iter().flat_map(ExtractPlan::chunk_refs).collect();
+            let chunks = self.store.read_chunk_map(&needed)?;
+            
+            let processed = plans[range.clone()].par_iter()
+                .zip(&manifest.files[range])
+                .map(|(plan, file)| {
+                    if file.is_compressed() {
+                        return Ok((file.clone(), Vec::new()));
+                    }
+                    let data = plan.assemble(|hash| &chunks[hash][..])?;
+                    let ext = Path::new(&file.path).extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or("")
+                        .to_lowercase();
+                    let (_, rechunked, prepared) = chunker.chunk_file(&file.path, &ext, &data, &strides)?;
+                    Ok((rechunked, prepared))
+                })
+                .collect::<Result<Vec<_>, StoreError>>()?;
+            
+            let mut prepared = Vec::new();
+            for (file, chunks) in processed {
+                files.push(file);
+                prepared.extend(chunks);
+            }
+            stored_size += self.store.put_pending(token, &prepared)?;
+        }
+        Ok((files, stored_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BackendKind;
+    use std::fs;
+    use tempfile::tempdir;
+    
+    fn sample_files(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        let files: Vec<(String, Vec<u8>)> = (0..4u32)
+            .map(|n| {
+                let data = (0..30_000u32).map(|i| ((i / 7 + n) % 251) as u8).collect();
+                (format!("f{}.dat", n), data)
+            })
+            .collect();
+        for (name, data) in &files {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        fs::write(dir.join("mod.ini"), "[mod]").unwrap();
+        files
+    }
+    
+    #[test]
+    fn test_recompress_resumes() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let files = sample_files(src.path());
+        
+        let mut archive = ModArchive::create(store.path(), BackendKind::Pack).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        assert!(archive.recompress(0).is_err());
+        
+        // 模拟在第一批之后中断：进度指向某个块，只有之后的块需要重新编码
+        let mut hashes = Vec::new();
+        archive.store.chunks.for_each(&mut |entry| {
+            hashes.push(entry.hash);
+            Ok(())
+        }).unwrap();
+        hashes.sort_unstable();
+        let progress = format!("zstd:19:{:032x}", hashes[1]);
+        schema::set_meta(&archive.store.conn, PROGRESS_KEY, &progress).unwrap();
+        
+        let report = archive.recompress(19).unwrap();
+        assert!(report.resumed);
+        assert_eq!(report.chunks, hashes.len());
+        assert_eq!(report.chunks_reencoded, hashes.len() - 2);
+        assert_eq!(archive.store.meta(PROGRESS_KEY).unwrap(), None);
+        
+        // 级别保存为存储配置
+        drop(archive);
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        assert_eq!(archive.config().compression_level, 19);
+        let report = archive.recompress(1).unwrap();
+        assert!(!report.resumed);
+        assert_eq!(report.chunks_reencoded, hashes.len());
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        for (name, data) in &files {
+            assert_eq!(&fs::read(out.path().join(name)).unwrap(), data);
+        }
+        assert!(archive.fsck(false).unwrap().is_clean());
+    }
+    
+    #[test]
+    fn test_rechunk() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let files = sample_files(src.path());
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        let created_at = archive.load_manifest("m").unwrap().unwrap().created_at;
+        
+        let strategy = ChunkingStrategy::Fixed { size: 16 * 1024 };
+        let report = archive.rechunk(strategy).unwrap();
+        assert_eq!((report.mods_rewritten, report.files_rechunked), (1, 4));
+        assert!(report.size_after < report.size_before, "{:?}", report);
+        
+        let manifest = archive.load_manifest("m").unwrap().unwrap();
+        assert_eq!(manifest.created_at, created_at);
+        assert!(manifest.files.iter().all(|f| f.chunking == Some(strategy)));
+        assert_eq!(archive.store.load_file_cache("m").unwrap().len(), 5);
+        
+        // 再次执行时跳过已经使用新策略的 mod；新策略保存为存储配置
+        assert_eq!(archive.rechunk(strategy).unwrap().mods_skipped, 1);
+        drop(archive);
+        let archive = ModArchive::open(store.path()).unwrap();
+        assert_eq!(archive.config().strategy, strategy);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        for (name, data) in &files {
+            assert_eq!(&fs::read(out.path().join(name)).unwrap(), data);
+        }
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+    }
+}
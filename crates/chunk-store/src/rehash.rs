@@ -0,0 +1,294 @@
+//! 更换块的 hash 算法
+//!
+//! 存储中所有块与清单使用同一种 hash 算法（`meta.hash_algorithm`），打开清单时拒绝算法不一致的清单。
+//! `rehash` 在两种算法之间迁移：每个块先按旧算法校验，再以新 hash 写入相同的编码数据；
+//! 文件校验和按旧算法校验后按新算法重新计算。新旧引用计数、所有清单和存储的算法在同一事务中切换，
+//! 提交前中断时新块的引用由恢复流程撤销，旧数据不受影响。
+
+use rayon::prelude::*;
+use rusqlite::params;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::archive::{parse_hash, size_groups, ExtractPlan, ModArchive, ModManifest};
+use crate::chunk::{HashAlgorithm, PreparedChunk};
+use crate::journal::Staging;
+use crate::schema;
+use crate::store::StoreError;
+
+/// 每批重新计算 hash 的块数
+const REHASH_BATCH: usize = 1024;
+
+/// 更换 hash 算法的结果
+#[derive(Debug, Default)]
+pub struct RehashReport {
+    pub chunks: usize,
+    /// 重写的清单数（含历史版本）
+    pub manifests: usize,
+    /// 校验并重新计算校验和的文件数
+    pub files_verified: usize,
+}
+
+/// 待重写的清单：mod id、历史版本号（当前清单为 `None`）和清单
+type StoredManifest = (String, Option<i64>, ModManifest);
+
+impl ModArchive {
+    /// 把存储中的块 hash、清单和文件校验和迁移到算法 `to`
+    ///
+    /// 任何块或文件与旧 hash 不符时中止，存储保持原样。完成后回收旧块。
+    pub fn rehash(&mut self, to: HashAlgorithm) -> Result<RehashReport, StoreError> {
+        let from = self.config.hash_algorithm;
+        let mut report = RehashReport::default();
+        if from == to {
+            return Ok(report);
+        }
+        
+        let mut manifests: Vec<StoredManifest> = Vec::new();
+        for (id, _, _) in self.list_mods()? {
+            for (version, manifest) in self.load_versions(&id)? {
+                manifests.push((id.clone(), Some(version), manifest));
+            }
+            if let Some(manifest) = self.load_manifest(&id)? {
+                manifests.push((id, None, manifest));
+            }
+        }
+        
+        let mut entries = Vec::new();
+        self.store.chunks.for_each(&mut |entry| {
+            entries.push((entry.hash, entry.ref_count));
+            Ok(())
+        })?;
+        
+        let staging = Staging::create(Path::new(self.store.base_path()))?;
+        let token = staging.token().to_string();
+        let result = self.rehash_chunks(&entries, from, to, &token)
+            .and_then(|mapping| {
+                let rewritten = manifests.iter()
+                    .map(|(id, version, manifest)| {
+                        let json = self.rehash_manifest(manifest, &mapping, to, &mut report)?;
+                        Ok((id.as_str(), *version, json))
+                    })
+                    .collect::<Result<Vec<_>, StoreError>>()?;
+                
+                let tx = self.store.conn.unchecked_transaction()?;
+                for &(old, ref_count) in &entries {
+                    if let Some(&new) = mapping.get(&old) {
+                        self.store.chunks.set_ref(new, ref_count)?;
+                        self.store.chunks.set_ref(old, 0)?;
+                    }
+                }
+                tx.execute("DELETE FROM pending_refs WHERE token = ?", [&token])?;
+                for (id, version, json) in &rewritten {
+                    match version {
+                        Some(version) => tx.execute(
+                            "UPDATE mod_versions SET manifest = ? WHERE id = ? AND version = ?",
+                            params![json, id, version],
+                        )?,
+                        None => tx.execute("UPDATE mods SET manifest = ? WHERE id = ?", params![json, id])?,
+                    };
+                }
+                // 缓存的校验和使用旧算法
+                tx.execute("DELETE FROM file_cache", [])?;
+                schema::set_meta(&tx, "hash_algorithm", to.name())?;
+                tx.commit()?;
+                
+                report.chunks = mapping.len();
+                report.manifests = rewritten.len();
+                Ok(())
+            });
+        
+        match result {
+            Ok(()) => {
+                staging.finish_committed(&self.store.conn);
+                self.config.hash_algorithm = to;
+                // 回收失败只会留下无引用的旧块，下次 `gc` 时删除
+                let _ = self.gc();
+                Ok(report)
+            }
+            Err(e) => {
+                staging.discard();
+                let _ = self.store.rollback_pending(Some(&token));
+                Err(e)
+            }
+        }
+    }
+    
+    /// 校验被引用的块并以新 hash 写入（引用登记在 `token` 下），返回旧 hash 到新 hash 的映射
+    fn rehash_chunks(
+        &mut self,
+        entries: &[(u128, i64)],
+        from: HashAlgorithm,
+        to: HashAlgorithm,
+        token: &str,
+    ) -> Result<HashMap<u128, u128>, StoreError> {
+        let existing: HashSet<u128> = entries.iter().map(|&(hash, _)| hash).collect();
+        // 未被引用的块随后由 gc 回收，不需要迁移
+        let referenced: Vec<u128> = entries.iter()
+            .filter(|&&(_, ref_count)| ref_count > 0)
+            .map(|&(hash, _)| hash)
+            .collect();
+        
+        let mut mapping = HashMap::with_capacity(referenced.len());
+        let mut seen = HashSet::with_capacity(referenced.len());
+        for batch in referenced.chunks(REHASH_BATCH) {
+            let data = self.store.read_chunk_map(batch)?;
+            let rehashed = batch.par_iter()
+                .map(|old| {
+                    let chunk = data.get(old)
+                        .ok_or_else(|| StoreError::ChunkNotFound(format!("{:032x}", old)))?;
+                    if from.hash(chunk) != *old {
+                        return Err(StoreError::HashAlgorithm(format!(
+                            "chunk {:032x} does not match its {} hash", old, from.name()
+                        )));
+                    }
+                    Ok((*old, to.hash(chunk)))
+                })
+                .collect::<Result<Vec<_>, StoreError>>()?;
+            
+            let mut raw = self.store.chunks.get_many(batch)?;
+            let mut prepared = Vec::with_capacity(rehashed.len());
+            for (old, new) in rehashed {
+                if existing.contains(&new) || !seen.insert(new) {
+                    return Err(StoreError::HashAlgorithm(format!(
+                        "{} hash collision for chunk {:032x}", to.name(), old
+                    )));
+                }
+                let (compressed, original_size, codec) = raw.remove(&old)
+                    .ok_or_else(|| StoreError::ChunkNotFound(format!("{:032x}", old)))?;
+                prepared.push(PreparedChunk { hash: new, compressed, original_size, codec });
+                mapping.insert(old, new);
+            }
+            self.store.put_pending(token, &prepared)?;
+        }
+        Ok(mapping)
+    }
+    
+    /// 按新算法重写清单：替换块 hash，校验并重新计算文件校验和
+    fn rehash_manifest(
+        &self,
+        manifest: &ModManifest,
+        mapping: &HashMap<u128, u128>,
+        to: HashAlgorithm,
+        report: &mut RehashReport,
+    ) -> Result<String, StoreError> {
+        let from = manifest.hash_algorithm;
+        let mut rewritten = manifest.clone();
+        rewritten.hash_algorithm = to;
+        
+        let map_hash = |hex: &mut String| -> Result<(), StoreError> {
+            let old = parse_hash(hex)?;
+            let new = mapping.get(&old).ok_or_else(|| StoreError::ChunkNotFound(hex.clone()))?;
+            *hex = format!("{:032x}", new);
+            Ok(())
+        };
+        for file in rewritten.files.iter_mut().filter(|f| !f.is_compressed()) {
+            if let Some(header) = file.dds_metadata.as_mut().and_then(|m| m.header_chunk.as_mut()) {
+                map_hash(header)?;
+            }
+            file.chunks.iter_mut().try_for_each(map_hash)?;
+        }
+        
+        // 只有记录了校验和的文件需要还原（旧版 DDS 清单的文件头可能依赖原始目录）
+        let checked: Vec<usize> = (0..manifest.files.len())
+            .filter(|&i| manifest.files[i].checksum.is_some())
+            .collect();
+        let plans: Vec<ExtractPlan> = checked.iter()
+            .map(|&i| self.extract_plan(manifest, &manifest.files[i]))
+            .collect::<Result<_, _>>()?;
+        
+        let budget = (self.memory_limit / 2).max(1) as u64;
+        for range in size_groups(&plans, budget) {
+            let needed: Vec<u128> = plans[range.clone()].iter().flat_map(ExtractPlan::chunk_refs).collect();
+            let chunks = self.store.read_chunk_map(&needed)?;
+            let checksums = plans[range.clone()].par_iter()
+                .zip(&checked[range])
+                .map(|(plan, &i)| {
+                    let data = plan.assemble(|hash| &chunks[hash][..])?;
+                    if manifest.files[i].checksum.as_deref() != Some(&format!("{:032x}", from.hash(&data))) {
+                        return Err(StoreError::HashAlgorithm(format!(
+                            "{} in {} does not match its checksum", plan.path, manifest.id
+                        )));
+                    }
+                    Ok((i, format!("{:032x}", to.hash(&data))))
+                })
+                .collect::<Result<Vec<_>, StoreError>>()?;
+            for (i, checksum) in checksums {
+                rewritten.files[i].checksum = Some(checksum);
+                report.files_verified += 1;
+            }
+        }
+        
+        serde_json::to_string(&rewritten).map_err(|e| StoreError::CorruptManifest(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{ArchiveOptions, OnExisting};
+    use std::fs;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_rehash_to_blake3() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(src.path().join("a.dat"), &data).unwrap();
+        fs::write(src.path().join("b.dat"), &data[..20_000]).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        fs::write(src.path().join("b.dat"), &data[10_000..]).unwrap();
+        let version = ArchiveOptions {
+            id: Some("m".to_string()),
+            on_existing: OnExisting::Version,
+            ..Default::default()
+        };
+        archive.archive_mod_with(src.path(), &version).unwrap();
+        
+        let report = archive.rehash(HashAlgorithm::Blake3).unwrap();
+        assert_eq!(report.manifests, 2);
+        assert_eq!(report.files_verified, 4);
+        assert_eq!(archive.rehash(HashAlgorithm::Blake3).unwrap().chunks, 0);
+        
+        drop(archive);
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        assert_eq!(archive.config().hash_algorithm, HashAlgorithm::Blake3);
+        let manifest = archive.load_manifest("m").unwrap().unwrap();
+        assert_eq!(manifest.hash_algorithm, HashAlgorithm::Blake3);
+        let chunks = archive.store.read_chunk_map(&manifest.chunk_refs().unwrap()).unwrap();
+        assert!(chunks.iter().all(|(hash, data)| HashAlgorithm::Blake3.hash(data) == *hash));
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("b.dat")).unwrap(), &data[10_000..]);
+        archive.extract_version("m", 1, out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("b.dat")).unwrap(), &data[..20_000]);
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+        assert!(archive.fsck(false).unwrap().is_clean());
+        
+        // 新归档的块沿用 BLAKE3，与已有的块去重
+        let before = archive.get_stats().unwrap().chunks.stored_size;
+        archive.archive_mod(src.path(), Some("copy"), None).unwrap();
+        assert_eq!(archive.get_stats().unwrap().chunks.stored_size, before);
+    }
+    
+    #[test]
+    fn test_reject_mismatched_manifest() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        fs::write(src.path().join("a.dat"), b"data").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        let (name, json) = archive.store.get_mod("m").unwrap().unwrap();
+        let mut manifest: ModManifest = serde_json::from_str(&json).unwrap();
+        manifest.hash_algorithm = HashAlgorithm::Blake3;
+        archive.store.save_mod("m", &name, &serde_json::to_string(&manifest).unwrap()).unwrap();
+        
+        assert!(matches!(archive.load_manifest("m"), Err(StoreError::HashAlgorithm(_))));
+        assert!(archive.rehash(HashAlgorithm::Blake3).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! store.db 的版本与迁移
+//!
+//! `meta` 表记录 schema 版本、存储格式版本、块大小、hash 算法和块后端。
+//! 打开存储时按顺序执行尚未应用的迁移，每个迁移在独立事务中完成，
+//! 与版本号的更新一起提交。没有 `meta` 表的旧存储视为版本 0。
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+
+use crate::buffer;
+use crate::chunk::ChunkConfig;
+use crate::store::{BackendKind, StoreError};
+
+/// 当前代码支持的存储格式版本（数据布局，与表结构无关）
+pub const FORMAT_VERSION: u32 = 1;
+
+/// 当前默认的块 hash 算法
+pub const HASH_ALGORITHM: &str = "xxh3-128";
+
+/// 单个迁移
+struct Migration {
+    version: u32,
+    description: &'static str,
+    /// 参数为迁移事务和存储根目录
+    apply: fn(&Transaction, &Path) -> Result<(), StoreError>,
+}
+
+/// 按版本顺序排列的迁移，只能追加
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS chunks (
+                hash BLOB PRIMARY KEY,
+                data BLOB NOT NULL,
+                original_size INTEGER NOT NULL,
+                ref_count INTEGER DEFAULT 1
+            );
+            
+            CREATE TABLE IF NOT EXISTS mods (
+                id TEXT PRIMARY KEY,
+                name TEXT,
+                manifest TEXT,
+                created_at INTEGER
+            );
+            
+            CREATE TABLE IF NOT EXISTS mod_versions (
+                id TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                name TEXT,
+                manifest TEXT,
+                created_at INTEGER,
+                PRIMARY KEY (id, version)
+            );
+            
+            CREATE TABLE IF NOT EXISTS pending_ops (
+                token TEXT PRIMARY KEY,
+                plan TEXT NOT NULL,
+                created_at INTEGER
+            );
+            
+            CREATE TABLE IF NOT EXISTS quarantined_chunks (
+                hash BLOB PRIMARY KEY,
+                data BLOB NOT NULL,
+                original_size INTEGER NOT NULL,
+                ref_count INTEGER,
+                quarantined_at INTEGER
+            );"
+        )?),
+    },
+    Migration {
+        version: 2,
+        description: "drop unused compressed_files table",
+        apply: |tx, _| Ok(tx.execute_batch("DROP TABLE IF EXISTS compressed_files;")?),
+    },
+    Migration {
+        version: 3,
+        description: "move buf/ib files from compressed/ into the chunk store",
+        apply: buffer::migrate_compressed,
+    },
+    Migration {
+        version: 4,
+        description: "zstd dictionaries per content class",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS dictionaries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                class TEXT NOT NULL,
+                data BLOB NOT NULL,
+                created_at INTEGER
+            );
+            
+            ALTER TABLE chunks ADD COLUMN dict_id INTEGER;
+            ALTER TABLE quarantined_chunks ADD COLUMN dict_id INTEGER;"
+        )?),
+    },
+    Migration {
+        version: 5,
+        description: "pack file chunk backend",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS packs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                size INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER
+            );
+            
+            ALTER TABLE chunks ADD COLUMN pack_id INTEGER;
+            ALTER TABLE chunks ADD COLUMN pack_offset INTEGER;
+            ALTER TABLE chunks ADD COLUMN pack_len INTEGER;
+            CREATE INDEX IF NOT EXISTS idx_chunks_pack ON chunks (pack_id, pack_offset);"
+        )?),
+    },
+    Migration {
+        version: 6,
+        description: "chunk references of archives in progress",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pending_refs (
+                token TEXT NOT NULL,
+                hashes BLOB NOT NULL,
+                created_at INTEGER
+            );"
+        )?),
+    },
+    Migration {
+        version: 7,
+        description: "file-level change cache for incremental re-archive",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS file_cache (
+                mod_id TEXT NOT NULL,
+                path TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                checksum TEXT,
+                PRIMARY KEY (mod_id, path)
+            );"
+        )?),
+    },
+    Migration {
+        version: 8,
+        description: "per-chunk codec tag",
+        apply: |tx, _| Ok(tx.execute_batch(
+            "ALTER TABLE chunks ADD COLUMN codec INTEGER;
+            ALTER TABLE quarantined_chunks ADD COLUMN codec INTEGER;"
+        )?),
+    },
+];
+
+/// 当前代码支持的 schema 版本
+pub fn schema_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// 初始化 meta 表并执行所有未应用的迁移，返回执行的迁移数
+///
+/// `backend` 只用于新建的存储，已有的存储没有记录时视为 SQLite 后端。
+pub fn migrate(conn: &mut Connection, base: &Path, backend: BackendKind) -> Result<usize, StoreError> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );"
+    )?;
+    
+    let found = get_u32(conn, "schema_version")?.unwrap_or(0);
+    let created: bool = found == 0 && conn.query_row(
+        "SELECT COUNT(*) = 0 FROM sqlite_master WHERE name = 'chunks'", [], |row| row.get(0)
+    )?;
+    let supported = schema_version();
+    if found > supported {
+        return Err(StoreError::UnsupportedVersion(format!(
+            "store schema version {} is newer than supported version {}", found, supported
+        )));
+    }
+    
+    let format = get_u32(conn, "format_version")?.unwrap_or(FORMAT_VERSION);
+    if format > FORMAT_VERSION {
+        return Err(StoreError::UnsupportedVersion(format!(
+            "store format version {} is newer than supported version {}", format, FORMAT_VERSION
+        )));
+    }
+    
+    let mut applied = 0;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > found) {
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx, base).map_err(|e| StoreError::Migration(format!(
+            "v{} ({}): {}", migration.version, migration.description, e
+        )))?;
+        set_meta(&tx, "schema_version", &migration.version.to_string())?;
+        tx.commit()?;
+        applied += 1;
+    }
+    
+    // 新存储（或版本 0 的旧存储）记录创建时的参数
+    let tx = conn.transaction()?;
+    insert_meta_default(&tx, "format_version", &FORMAT_VERSION.to_string())?;
+    insert_meta_default(&tx, "chunk_size", &ChunkConfig::default().chunk_size().to_string())?;
+    insert_meta_default(&tx, "hash_algorithm", HASH_ALGORITHM)?;
+    let backend = if created { backend } else { BackendKind::Sqlite };
+    insert_meta_default(&tx, "chunk_backend", backend.as_str())?;
+    tx.commit()?;
+    
+    Ok(applied)
+}
+
+/// 读取 meta 值
+pub fn get_meta(conn: &Connection, key: &str) -> Result<Option<String>, StoreError> {
+    Ok(conn
+        .query_row("SELECT value FROM meta WHERE key = ?", [key], |row| row.get(0))
+        .optional()?)
+}
+
+/// 写入 meta 值
+pub fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<(), StoreError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn insert_meta_default(conn: &Connection, key: &str, value: &str) -> Result<(), StoreError> {
+    conn.execute(
+        "INSERT OR IGNORE INTO meta (key, value) VALUES (?, ?)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn get_u32(conn: &Connection, key: &str) -> Result<Option<u32>, StoreError> {
+    match get_meta(conn, key)? {
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            StoreError::UnsupportedVersion(format!("invalid {} in meta: {}", key, value))
+        }),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkStore;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_migrate_legacy_store() {
+        let dir = tempdir().unwrap();
+        
+        // 没有 meta 表的旧存储
+        let conn = Connection::open(dir.path().join("store.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE chunks (hash BLOB PRIMARY KEY, data BLOB NOT NULL,
+                original_size INTEGER NOT NULL, ref_count INTEGER DEFAULT 1);
+             CREATE TABLE mods (id TEXT PRIMARY KEY, name TEXT, manifest TEXT, created_at INTEGER);
+             CREATE TABLE compressed_files (id TEXT PRIMARY KEY);"
+        ).unwrap();
+        drop(conn);
+        
+        let store = ChunkStore::open(dir.path()).unwrap();
+        assert_eq!(store.meta("schema_version").unwrap(), Some(schema_version().to_string()));
+        assert_eq!(store.meta("hash_algorithm").unwrap().as_deref(), Some(HASH_ALGORITHM));
+        let dropped: i64 = store.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'compressed_files'", [], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(dropped, 0);
+    }
+    
+    #[test]
+    fn test_reject_newer_store() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        set_meta(&store.conn, "schema_version", &(schema_version() + 1).to_string()).unwrap();
+        drop(store);
+        
+        assert!(matches!(
+            ChunkStore::open(dir.path()),
+            Err(StoreError::UnsupportedVersion(_))
+        ));
+    }
+}
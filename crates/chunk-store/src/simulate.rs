@@ -0,0 +1,236 @@
+//! 分块配置模拟
+//!
+//! `simulate` 还原存储中的所有文件（含历史版本），按若干候选配置重新分块和压缩，
+//! 统计去重率、索引大小和解码吞吐量，不写入任何数据。用于按实际的 mod 库调整 `ChunkConfig`。
+
+use rayon::prelude::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zstd::dict::DecoderDictionary;
+
+use crate::archive::{size_groups, ExtractPlan, FileChunker, FileManifest, ModArchive, ModManifest};
+use crate::buffer::load_strides;
+use crate::chunk::{decode_chunk, ChunkConfig, PreparedChunk};
+use crate::store::{ChunkStore, StoreError};
+
+/// 块表每行的估算大小：hash、大小、引用计数、编码和包位置，加上 SQLite 的行与索引开销
+const CHUNK_ROW_SIZE: u64 = 80;
+
+/// 清单中每个块引用的大小：32 位十六进制 hash 加引号和逗号
+const CHUNK_REF_SIZE: u64 = 35;
+
+/// 单个配置的模拟结果
+#[derive(Debug, Clone)]
+pub struct SimulationResult {
+    pub config: ChunkConfig,
+    /// 重放的原始数据大小
+    pub logical_size: u64,
+    pub unique_chunks: usize,
+    /// 清单中的块引用数
+    pub chunk_refs: u64,
+    /// 不同的块压缩后的大小
+    pub stored_size: u64,
+    /// 估算的索引大小：块表的行与清单中的块引用
+    pub index_size: u64,
+    /// 单线程解码所有块引用的实测吞吐量（字节/秒），不含磁盘读取，用于估计提取速度
+    pub decode_throughput: f64,
+}
+
+impl SimulationResult {
+    pub fn deduplication_ratio(&self) -> f64 {
+        if self.logical_size == 0 {
+            0.0
+        } else {
+            1.0 - (self.stored_size as f64 / self.logical_size as f64)
+        }
+    }
+}
+
+/// 单个配置的模拟状态
+struct Simulation {
+    config: ChunkConfig,
+    chunker: FileChunker,
+    /// 已出现的块及其压缩后的大小
+    chunks: HashMap<u128, u32>,
+    chunk_refs: u64,
+    stored_size: u64,
+    decoded_size: u64,
+    decode_time: Duration,
+}
+
+impl Simulation {
+    /// 分块压缩一批文件，再逐块解码计时
+    fn replay(
+        &mut self,
+        store: &ChunkStore,
+        files: &[(&FileManifest, Vec<u8>)],
+        strides: &HashMap<String, usize>,
+        decoders: &mut HashMap<i64, Arc<DecoderDictionary<'static>>>,
+    ) -> Result<(), StoreError> {
+        let chunker = &self.chunker;
+        let prepared = files.par_iter()
+            .map(|(file, data)| {
+                let ext = Path::new(&file.path).extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("")
+                    .to_lowercase();
+                let (_, _, prepared) = chunker.chunk_file(&file.path, &ext, data, strides)?;
+                Ok(prepared)
+            })
+            .collect::<Result<Vec<Vec<PreparedChunk>>, StoreError>>()?;
+        
+        for chunk in prepared.iter().flatten() {
+            if let Some(id) = chunk.codec.dict_id() {
+                if let Entry::Vacant(e) = decoders.entry(id) {
+                    e.insert(store.decoder_dictionary(id)?);
+                }
+            }
+        }
+        
+        let start = Instant::now();
+        for chunk in prepared.iter().flatten() {
+            let dict = chunk.codec.dict_id().map(|id| &*decoders[&id]);
+            self.decoded_size += decode_chunk(&chunk.compressed, chunk.original_size, chunk.codec, dict)?.len() as u64;
+        }
+        self.decode_time += start.elapsed();
+        
+        for chunk in prepared.iter().flatten() {
+            self.chunk_refs += 1;
+            if let Entry::Vacant(e) = self.chunks.entry(chunk.hash) {
+                e.insert(chunk.compressed.len() as u32);
+                self.stored_size += chunk.compressed.len() as u64;
+            }
+        }
+        Ok(())
+    }
+    
+    fn finish(self, logical_size: u64) -> SimulationResult {
+        let seconds = self.decode_time.as_secs_f64();
+        SimulationResult {
+            config: self.config,
+            logical_size,
+            unique_chunks: self.chunks.len(),
+            chunk_refs: self.chunk_refs,
+            stored_size: self.stored_size,
+            index_size: self.chunks.len() as u64 * CHUNK_ROW_SIZE + self.chunk_refs * CHUNK_REF_SIZE,
+            decode_throughput: if seconds > 0.0 { self.decoded_size as f64 / seconds } else { 0.0 },
+        }
+    }
+}
+
+impl ModArchive {
+    /// 按每个候选配置模拟重新分块整个存储
+    ///
+    /// `max_bytes` 限制重放的数据量：mod 按顺序完整重放，达到上限后不再加入新的 mod。
+    /// 每批还原的数据不超过内存上限的四分之一；每个配置在内存中保存所有不同块的 hash。
+    pub fn simulate(
+        &self,
+        configs: &[ChunkConfig],
+        max_bytes: Option<u64>,
+    ) -> Result<Vec<SimulationResult>, StoreError> {
+        let mut simulations = configs.iter()
+            .map(|config| {
+                config.strategy.validate()?;
+                Ok(Simulation {
+                    config: config.clone(),
+                    chunker: self.chunker_with(config.clone())?,
+                    chunks: HashMap::new(),
+                    chunk_refs: 0,
+                    stored_size: 0,
+                    decoded_size: 0,
+                    decode_time: Duration::ZERO,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        
+        let budget = (self.memory_limit / 4).max(1) as u64;
+        let mut decoders = HashMap::new();
+        let mut logical_size = 0u64;
+        for (id, _, _) in self.list_mods()? {
+            if max_bytes.is_some_and(|max| logical_size >= max) {
+                break;
+            }
+            
+            // buf/ib 的元素大小取自与清单一起保存的 ini
+            let mut manifests: Vec<(PathBuf, ModManifest)> = self.load_versions(&id)?
+                .into_iter()
+                .map(|(version, manifest)| (self.version_dir(&id, version), manifest))
+                .collect();
+            if let Some(manifest) = self.load_manifest(&id)? {
+                manifests.push((self.preserved_dir(&id), manifest));
+            }
+            
+            for (dir, manifest) in &manifests {
+                let strides = load_strides(dir, &manifest.preserved_files);
+                let plans: Vec<ExtractPlan> = manifest.files.iter()
+                    .map(|file| self.extract_plan(manifest, file))
+                    .collect::<Result<_, _>>()?;
+                for range in size_groups(&plans, budget) {
+                    let needed: Vec<u128> = plans[range.clone()].iter().flat_map(ExtractPlan::chunk_refs).collect();
+                    let chunks = self.store.read_chunk_map(&needed)?;
+                    let files = plans[range.clone()].par_iter()
+                        .zip(&manifest.files[range])
+                        .map(|(plan, file)| Ok((file, plan.assemble(|hash| &chunks[hash][..])?)))
+                        .collect::<Result<Vec<_>, StoreError>>()?;
+                    drop(chunks);
+                    
+                    logical_size += files.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+                    for simulation in &mut simulations {
+                        simulation.replay(&self.store, &files, &strides, &mut decoders)?;
+                    }
+                }
+            }
+        }
+        
+        Ok(simulations.into_iter().map(|s| s.finish(logical_size)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{hash_chunk, ChunkingStrategy};
+    use std::fs;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_simulate_configs() {
+        let store = tempdir().unwrap();
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        // 相同的 4KB 块与不可压缩的块交替出现：4KB 分块可以去重，64KB 分块不能
+        let repeated: Vec<u8> = (0..4096u32).map(|i| (i % 13) as u8).collect();
+        let data: Vec<u8> = (0..16u32)
+            .flat_map(|n| match n % 2 {
+                0 => repeated.clone(),
+                _ => (0..4096u32).map(|i| hash_chunk(&(n * 4096 + i).to_le_bytes()) as u8).collect(),
+            })
+            .collect();
+        fs::write(first.path().join("a.dat"), &data).unwrap();
+        fs::write(second.path().join("b.dat"), &data[..8192]).unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(first.path(), Some("a"), None).unwrap();
+        archive.archive_mod(second.path(), Some("b"), None).unwrap();
+        let before = archive.get_stats().unwrap();
+        
+        let configs: Vec<ChunkConfig> = [4096, 65536]
+            .map(|size| ChunkConfig { strategy: ChunkingStrategy::Fixed { size }, ..Default::default() })
+            .into();
+        let results = archive.simulate(&configs, None).unwrap();
+        let (small, large) = (&results[0], &results[1]);
+        assert_eq!(small.logical_size, data.len() as u64 + 8192);
+        assert_eq!((small.unique_chunks, small.chunk_refs), (9, 18));
+        assert!(small.deduplication_ratio() > large.deduplication_ratio());
+        assert!(small.index_size > large.index_size);
+        assert!(small.decode_throughput > 0.0);
+        
+        // 达到上限后不再加入新的 mod
+        let limited = archive.simulate(&configs[..1], Some(1)).unwrap();
+        assert!([data.len() as u64, 8192].contains(&limited[0].logical_size));
+        assert_eq!(archive.get_stats().unwrap().unique_chunks, before.unique_chunks);
+    }
+}
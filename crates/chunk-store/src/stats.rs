@@ -0,0 +1,345 @@
+//! 存储空间统计
+//!
+//! 逐个解析清单（含历史版本）计算逻辑字节数，物理字节数取自块后端、
+//! `compressed/`、`mods/`、`versions/` 目录和 `store.db` 文件的实际大小，
+//! 不做任何平均值估算。
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::archive::{parse_manifest, ModManifest};
+use crate::store::{ChunkStore, StoreError};
+
+/// 某一存储类别的字节数
+#[derive(Debug, Default, Clone)]
+pub struct ClassStats {
+    /// 条目数（块、压缩文件、保留文件或清单）
+    pub items: usize,
+    /// 所有清单展开后的原始大小
+    pub logical_size: u64,
+    /// 磁盘上实际占用的大小
+    pub stored_size: u64,
+}
+
+impl ClassStats {
+    pub fn deduplication_ratio(&self) -> f64 {
+        dedup_ratio(self.stored_size, self.logical_size)
+    }
+}
+
+/// 使用某种编码的块
+#[derive(Debug, Default, Clone)]
+pub struct CodecStats {
+    pub chunks: usize,
+    pub original_size: u64,
+    pub stored_size: u64,
+}
+
+/// 单个 mod 的空间占用（历史版本的占用计入所属 mod）
+#[derive(Debug, Default, Clone)]
+pub struct ModUsage {
+    pub id: String,
+    pub name: String,
+    /// 当前版本的原始大小
+    pub total_size: u64,
+    /// 只被该 mod 引用的存储大小（删除后可回收）
+    pub exclusive_size: u64,
+    /// 与其他 mod 共享的块的存储大小
+    pub shared_size: u64,
+}
+
+/// 存储统计
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    pub mod_count: usize,
+    pub version_count: usize,
+    pub unique_chunks: usize,
+    /// 块、压缩文件与保留文件的存储大小之和（不含元数据）
+    pub total_stored_size: u64,
+    /// 块、压缩文件与保留文件的原始大小之和
+    pub total_original_size: u64,
+    /// 块（包文件中尚未压缩回收的失效字节也计入存储大小）
+    pub chunks: ClassStats,
+    /// 旧版 buf/ib 整体压缩文件（`compressed/*.zst`，迁移失败时保留）
+    pub compressed: ClassStats,
+    /// 原样保留的文件
+    pub preserved: ClassStats,
+    /// 清单与索引（`store.db` 中除块数据以外的部分）
+    pub metadata: ClassStats,
+    /// 引用计数为 0 的块、包文件中的失效字节和隔离块，`gc` 或清理隔离区后可回收
+    pub reclaimable_size: u64,
+    /// 按编码统计的块（键为编码名称）
+    pub codecs: BTreeMap<&'static str, CodecStats>,
+    pub mods: Vec<ModUsage>,
+    /// 无法解析、未计入统计的清单数（`verify_all` 会报告具体错误）
+    pub corrupt_manifests: usize,
+}
+
+impl StoreStats {
+    pub fn deduplication_ratio(&self) -> f64 {
+        dedup_ratio(self.total_stored_size, self.total_original_size)
+    }
+}
+
+fn dedup_ratio(stored: u64, original: u64) -> f64 {
+    if original == 0 {
+        0.0
+    } else {
+        1.0 - (stored as f64 / original as f64)
+    }
+}
+
+impl ChunkStore {
+    /// 精确统计各存储类别和每个 mod 的空间占用
+    pub fn get_stats(&self) -> Result<StoreStats, StoreError> {
+        let mut stats = StoreStats::default();
+        let base = PathBuf::from(self.base_path());
+        
+        // 所有块的存储大小
+        let mut chunk_sizes: HashMap<u128, u64> = HashMap::new();
+        self.chunks.for_each(&mut |entry| {
+            stats.chunks.items += 1;
+            stats.chunks.stored_size += entry.stored_size;
+            if entry.ref_count <= 0 {
+                stats.reclaimable_size += entry.stored_size;
+            }
+            chunk_sizes.insert(entry.hash, entry.stored_size);
+            let codec = stats.codecs.entry(entry.codec.name()).or_default();
+            codec.chunks += 1;
+            codec.original_size += entry.original_size as u64;
+            codec.stored_size += entry.stored_size;
+            Ok(())
+        })?;
+        stats.unique_chunks = stats.chunks.items;
+        let garbage = self.chunks.garbage_size()?;
+        stats.chunks.stored_size += garbage;
+        stats.reclaimable_size += garbage;
+        
+        // store.db 中的块数据（SQLite 后端）
+        let blob_size: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(LENGTH(data)), 0) FROM chunks",
+            [],
+            |row| row.get(0),
+        )?;
+        let quarantined: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(LENGTH(data)), 0) FROM quarantined_chunks",
+            [],
+            |row| row.get(0),
+        )?;
+        stats.reclaimable_size += quarantined as u64;
+        
+        // 当前清单与历史版本
+        let rows: Vec<(String, String, Option<i64>, String)> = {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, name, NULL, manifest FROM mods
+                 UNION ALL SELECT id, name, version, manifest FROM mod_versions
+                 ORDER BY 1"
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        
+        // 每个块被哪些 mod 引用（按 `stats.mods` 中的下标；同一 mod 的清单相邻）
+        let mut owners: HashMap<u128, Vec<usize>> = HashMap::new();
+        let mut mod_index: HashMap<String, usize> = HashMap::new();
+        let mut compressed_seen: HashSet<String> = HashSet::new();
+        
+        for (id, name, version, manifest_json) in rows {
+            stats.metadata.items += 1;
+            stats.metadata.logical_size += manifest_json.len() as u64;
+            let label = match version {
+                Some(v) => format!("{}@{}", id, v),
+                None => id.clone(),
+            };
+            // 损坏的清单跳过，不影响其余清单的统计
+            let parsed = parse_manifest(&label, &manifest_json)
+                .and_then(|manifest: ModManifest| Ok((manifest.chunk_refs()?, manifest)));
+            let Ok((refs, manifest)) = parsed else {
+                stats.corrupt_manifests += 1;
+                continue;
+            };
+            
+            let index = *mod_index.entry(id.clone()).or_insert_with(|| {
+                stats.mods.push(ModUsage { id: id.clone(), name: name.clone(), ..Default::default() });
+                stats.mods.len() - 1
+            });
+            match version {
+                Some(_) => stats.version_count += 1,
+                None => {
+                    stats.mod_count += 1;
+                    stats.mods[index].name = name;
+                    stats.mods[index].total_size = manifest.original_size;
+                }
+            }
+            
+            for file in &manifest.files {
+                if file.is_compressed() {
+                    stats.compressed.logical_size += file.original_size;
+                } else {
+                    stats.chunks.logical_size += file.original_size;
+                }
+            }
+            for hash in refs {
+                let mods = owners.entry(hash).or_default();
+                if mods.last() != Some(&index) {
+                    mods.push(index);
+                }
+            }
+            
+            // 压缩文件不跨 mod 共享，但可能被同一 mod 的多个版本引用
+            for file_id in manifest.compressed_ids() {
+                if compressed_seen.insert(file_id.to_string()) {
+                    let size = file_size(&base.join("compressed").join(format!("{}.zst", file_id)))?;
+                    stats.compressed.items += 1;
+                    stats.compressed.stored_size += size;
+                    stats.mods[index].exclusive_size += size;
+                }
+            }
+        }
+        
+        for (hash, mods) in &owners {
+            let size = chunk_sizes.get(hash).copied().unwrap_or(0);
+            for &i in mods {
+                if mods.len() == 1 {
+                    stats.mods[i].exclusive_size += size;
+                } else {
+                    stats.mods[i].shared_size += size;
+                }
+            }
+        }
+        
+        // 保留文件没有去重，逻辑大小等于存储大小
+        for usage in &mut stats.mods {
+            let (count, size) = dir_size(&base.join("mods").join(&usage.id))?;
+            let (version_count, version_size) = dir_size(&base.join("versions").join(&usage.id))?;
+            stats.preserved.items += count + version_count;
+            stats.preserved.logical_size += size + version_size;
+            usage.exclusive_size += size + version_size;
+        }
+        stats.preserved.stored_size = stats.preserved.logical_size;
+        
+        // 数据库文件中除块数据以外的部分
+        let db_size = file_size(&base.join("store.db"))? + file_size(&base.join("store.db-wal"))?;
+        stats.metadata.stored_size = db_size
+            .saturating_sub(blob_size as u64)
+            .saturating_sub(quarantined as u64);
+        
+        stats.total_original_size =
+            stats.chunks.logical_size + stats.compressed.logical_size + stats.preserved.logical_size;
+        stats.total_stored_size =
+            stats.chunks.stored_size + stats.compressed.stored_size + stats.preserved.stored_size;
+        
+        Ok(stats)
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64, StoreError> {
+    match fs::metadata(path) {
+        Ok(meta) => Ok(meta.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 目录下的文件数和总大小（目录不存在时为 0）
+fn dir_size(dir: &Path) -> Result<(usize, u64), StoreError> {
+    if !dir.exists() {
+        return Ok((0, 0));
+    }
+    let mut count = 0;
+    let mut size = 0;
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if entry.file_type().is_file() {
+            count += 1;
+            size += entry.metadata().map_err(std::io::Error::from)?.len();
+        }
+    }
+    Ok((count, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{hash_chunk, BackendKind, Codec, ModArchive};
+    use std::fs;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_exact_accounting() {
+        let a = tempdir().unwrap();
+        let b = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        
+        let shared: Vec<u8> = (0..8192u32).map(|i| (i % 251) as u8).collect();
+        let own: Vec<u8> = (0..4096u32).map(|i| (i % 13) as u8).collect();
+        fs::write(a.path().join("shared.dat"), &shared).unwrap();
+        fs::write(b.path().join("shared.dat"), &shared).unwrap();
+        fs::write(b.path().join("own.dat"), &own).unwrap();
+        fs::write(b.path().join("mesh.buf"), &own).unwrap();
+        fs::write(b.path().join("mod.ini"), b"[Constants]").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(a.path(), Some("a"), None).unwrap();
+        archive.archive_mod(b.path(), Some("b"), None).unwrap();
+        
+        let stats = archive.get_stats().unwrap();
+        assert_eq!(stats.mod_count, 2);
+        assert_eq!(stats.unique_chunks, 3);
+        assert_eq!(stats.chunks.logical_size, 8192 * 2 + 4096 * 2);
+        assert_eq!(stats.compressed.logical_size, 0);
+        assert_eq!(stats.preserved.logical_size, 11);
+        assert_eq!(stats.total_original_size, 8192 * 2 + 4096 * 2 + 11);
+        
+        let chunk_bytes: i64 = archive.store.conn
+            .query_row("SELECT SUM(LENGTH(data)) FROM chunks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stats.chunks.stored_size, chunk_bytes as u64);
+        
+        let (ua, ub) = (&stats.mods[0], &stats.mods[1]);
+        assert_eq!((ua.id.as_str(), ua.total_size), ("a", 8192));
+        assert_eq!(ua.exclusive_size, 0);
+        assert_eq!(ua.shared_size, ub.shared_size);
+        assert_eq!(
+            ub.exclusive_size + ub.shared_size,
+            stats.chunks.stored_size + stats.compressed.stored_size + 11
+        );
+        
+        // 损坏的清单单独计数，其余统计不变
+        archive.archive_mod(a.path(), Some("c"), None).unwrap();
+        archive.store.conn.execute("UPDATE mods SET manifest = '{' WHERE id = 'c'", []).unwrap();
+        let damaged = archive.get_stats().unwrap();
+        assert_eq!((damaged.mod_count, damaged.corrupt_manifests), (2, 1));
+        assert_eq!(damaged.total_original_size, stats.total_original_size);
+        assert_eq!(damaged.chunks.stored_size, stats.chunks.stored_size);
+    }
+    
+    #[test]
+    fn test_codec_mix() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        
+        // 伪随机数据压缩后不会变小，按原样保存
+        let noise: Vec<u8> = (0..16_384u32).map(|i| hash_chunk(&i.to_le_bytes()) as u8).collect();
+        let text = "float4 main() { return 0; }\n".repeat(400);
+        fs::write(src.path().join("noise.bin"), &noise).unwrap();
+        fs::write(src.path().join("shader.hlsl"), &text).unwrap();
+        
+        let mut archive = ModArchive::create(store.path(), BackendKind::Pack).unwrap();
+        archive.set_codec(Codec::Lz4).unwrap();
+        assert!(archive.set_codec(Codec::ZstdDict(1)).is_err());
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        let stats = archive.get_stats().unwrap();
+        assert_eq!(stats.codecs["raw"].chunks, 4);
+        assert_eq!(stats.codecs["raw"].stored_size, noise.len() as u64);
+        assert!(stats.codecs["lz4"].stored_size < stats.codecs["lz4"].original_size);
+        
+        archive.extract_mod("m", out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("noise.bin")).unwrap(), noise);
+        assert_eq!(fs::read_to_string(out.path().join("shader.hlsl")).unwrap(), text);
+        assert!(archive.verify_mod("m").unwrap().is_ok());
+    }
+}
@@ -1,10 +1,20 @@
 //! SQLite 存储模块
 
-use rusqlite::{Connection, params};
+use rayon::prelude::*;
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
 use thiserror::Error;
+use zstd::dict::DecoderDictionary;
 
-use crate::chunk::PreparedChunk;
+use crate::backend::{ChunkBackend, DirectoryBackend, SqliteBackend};
+use crate::chunk::{decode_chunk, ChunkConfig, ChunkingStrategy, Codec, HashAlgorithm, PreparedChunk};
+use crate::journal;
+use crate::schema;
 
 #[derive(Error, Debug)]
 pub enum StoreError {
@@ -14,41 +24,147 @@ pub enum StoreError {
     Io(#[from] std::io::Error),
     #[error("Chunk not found: {0}")]
     ChunkNotFound(String),
+    #[error("Mod not found: {0}")]
+    ModNotFound(String),
+    #[error("Mod already exists: {0}")]
+    ModExists(String),
+    #[error("Corrupt manifest: {0}")]
+    CorruptManifest(String),
+    #[error("Compression error: {0}")]
+    Compression(String),
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+    #[error("Corrupt journal: {0}")]
+    CorruptJournal(String),
+    #[error("Unsupported store version: {0}")]
+    UnsupportedVersion(String),
+    #[error("Migration failed: {0}")]
+    Migration(String),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Dictionary not found: {0}")]
+    DictionaryNotFound(String),
+    #[error("Hash algorithm mismatch: {0}")]
+    HashAlgorithm(String),
+    #[error("Preserved file not found: {0}")]
+    PreservedFileNotFound(String),
 }
 
-/// 存储统计
-#[derive(Debug, Default)]
-pub struct StoreStats {
-    pub mod_count: usize,
-    pub unique_chunks: usize,
-    pub total_stored_size: u64,
-    pub total_original_size: u64,
+/// 新写入的块保存在哪里
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendKind {
+    /// 每个块一行 `chunks.data` BLOB
+    #[default]
+    Sqlite,
+    /// 追加到 `packs/` 下的包文件，SQLite 只保存索引
+    Pack,
+    /// 每个块一个文件，保存在 `chunks/` 下
+    Directory,
 }
 
-impl StoreStats {
-    pub fn deduplication_ratio(&self) -> f64 {
-        if self.total_original_size == 0 {
-            0.0
-        } else {
-            1.0 - (self.total_stored_size as f64 / self.total_original_size as f64)
+impl BackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Sqlite => "sqlite",
+            BackendKind::Pack => "pack",
+            BackendKind::Directory => "directory",
+        }
+    }
+    
+    pub fn parse(value: &str) -> Result<Self, StoreError> {
+        match value {
+            "sqlite" => Ok(BackendKind::Sqlite),
+            "pack" => Ok(BackendKind::Pack),
+            "directory" => Ok(BackendKind::Directory),
+            other => Err(StoreError::InvalidConfig(format!("unknown chunk backend: {}", other))),
         }
     }
 }
 
+/// 一次 mod 提交涉及的数据库变更
+pub(crate) struct ModCommit<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    /// 未改动、沿用旧清单条目的文件的块引用
+    pub reused: &'a [u128],
+    /// 被替换清单的块引用，提交时释放
+    pub released: &'a [u128],
+    /// 把旧清单保存为该历史版本
+    pub keep_version: Option<i64>,
+    /// 文件操作日志 (token, plan)，token 同时标识归档时登记的块引用
+    pub journal: (&'a str, &'a str),
+    /// 新清单中文件的缓存记录
+    pub cache: &'a [CachedFile],
+    /// 清单的创建时间（重新分块时沿用原值）
+    pub created_at: i64,
+}
+
+/// 已归档文件的缓存记录，大小和修改时间不变的文件重新归档时不再读取
+#[derive(Debug, Clone)]
+pub(crate) struct CachedFile {
+    pub path: String,
+    pub size: u64,
+    /// 修改时间（Unix 纪元以来的纳秒）
+    pub mtime: i64,
+    /// 按存储的 hash 算法计算的文件内容校验和
+    pub checksum: Option<String>,
+}
+
+/// `read_chunk_map` 每批读取和解码的块数
+const READ_BATCH: usize = 1024;
+
 /// 块存储
 pub struct ChunkStore {
-    conn: Connection,
+    /// 与 `SqliteBackend` 共用，块写入与清单在同一事务中提交
+    pub(crate) conn: Rc<Connection>,
     base_path: String,
+    /// 已加载的解压字典
+    dictionaries: RefCell<HashMap<i64, Arc<DecoderDictionary<'static>>>>,
+    pub(crate) chunks: Box<dyn ChunkBackend>,
+    /// `store.lock` 的共享锁，持有期间其他进程不会恢复（撤销）本进程未提交的归档
+    lock: File,
 }
 
+/// 未解码的块：(编码后的数据, 原始大小, 编码方式)
+pub type RawChunk = (Vec<u8>, usize, Codec);
+
 impl ChunkStore {
-    /// 创建或打开存储
+    /// 打开存储（不存在时使用 SQLite 后端创建）
     pub fn open<P: AsRef<Path>>(base_path: P) -> Result<Self, StoreError> {
-        let base_path = base_path.as_ref();
+        Self::open_inner(base_path.as_ref(), BackendKind::default(), None)
+    }
+    
+    /// 使用指定后端创建存储；已存在的存储必须使用相同的后端
+    pub fn create<P: AsRef<Path>>(base_path: P, backend: BackendKind) -> Result<Self, StoreError> {
+        let store = Self::open_inner(base_path.as_ref(), backend, None)?;
+        if store.chunks.name() != backend.as_str() {
+            return Err(StoreError::InvalidConfig(format!(
+                "store already uses the {} backend", store.chunks.name()
+            )));
+        }
+        Ok(store)
+    }
+    
+    /// 使用自定义的块后端打开存储（清单与元数据仍在 `store.db` 中）
+    pub fn with_backend<P: AsRef<Path>>(base_path: P, backend: Box<dyn ChunkBackend>) -> Result<Self, StoreError> {
+        Self::open_inner(base_path.as_ref(), BackendKind::default(), Some(backend))
+    }
+    
+    /// 打开存储并恢复未完成的操作；`custom` 为 `None` 时按元数据中记录的后端打开块数据
+    fn open_inner(
+        base_path: &Path,
+        backend: BackendKind,
+        custom: Option<Box<dyn ChunkBackend>>,
+    ) -> Result<Self, StoreError> {
         std::fs::create_dir_all(base_path)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(base_path.join("store.lock"))?;
         
         let db_path = base_path.join("store.db");
-        let conn = Connection::open(&db_path)?;
+        let mut conn = Connection::open(&db_path)?;
         
         // 性能优化
         conn.execute_batch(
@@ -58,135 +174,272 @@ impl ChunkStore {
              PRAGMA busy_timeout = 30000;"
         )?;
         
-        let store = Self {
+        schema::migrate(&mut conn, base_path, backend)?;
+        let backend = BackendKind::parse(&schema::get_meta(&conn, "chunk_backend")?.unwrap_or_default())?;
+        
+        let conn = Rc::new(conn);
+        // 恢复日志时必须使用实际保存块的后端
+        let chunks: Box<dyn ChunkBackend> = match (custom, backend) {
+            (Some(custom), _) => custom,
+            (None, BackendKind::Sqlite | BackendKind::Pack) => {
+                Box::new(SqliteBackend::new(conn.clone(), base_path, backend))
+            }
+            (None, BackendKind::Directory) => Box::new(DirectoryBackend::open(base_path.join("chunks"))?),
+        };
+        let mut store = Self {
             conn,
             base_path: base_path.to_string_lossy().to_string(),
+            dictionaries: RefCell::new(HashMap::new()),
+            chunks,
+            lock,
         };
-        store.init_schema()?;
+        
+        // 未提交的暂存区和块引用登记可能属于其他正在归档的进程，
+        // 只有没有其他进程打开存储（能取得排他锁）时才恢复
+        match store.lock.try_lock() {
+            Ok(()) => {
+                journal::recover(&mut store)?;
+                store.lock.unlock()?;
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        store.lock.lock_shared()?;
         
         Ok(store)
     }
     
-    fn init_schema(&self) -> Result<(), StoreError> {
-        self.conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS chunks (
-                hash BLOB PRIMARY KEY,
-                data BLOB NOT NULL,
-                original_size INTEGER NOT NULL,
-                ref_count INTEGER DEFAULT 1
-            );
-            
-            CREATE TABLE IF NOT EXISTS mods (
-                id TEXT PRIMARY KEY,
-                name TEXT,
-                manifest TEXT,
-                created_at INTEGER
-            );
-            
-            CREATE TABLE IF NOT EXISTS compressed_files (
-                id TEXT PRIMARY KEY,
-                mod_id TEXT,
-                original_path TEXT,
-                original_size INTEGER,
-                compressed_size INTEGER
-            );"
-        )?;
-        Ok(())
-    }
-    
     /// 批量存储块（去重）
     pub fn store_chunks_batch(&mut self, chunks: &[PreparedChunk]) -> Result<u64, StoreError> {
-        let mut stored_size = 0u64;
+        self.chunks.put(chunks)
+    }
+    
+    /// 写入归档过程中的一批块
+    ///
+    /// 块引用登记在 `pending_refs` 中，清单提交时删除登记；
+    /// 提交前失败或崩溃时由 `rollback_pending` 撤销。返回新写入的压缩字节数。
+    pub(crate) fn put_pending(&mut self, token: &str, chunks: &[PreparedChunk]) -> Result<u64, StoreError> {
+        let tx = self.conn.unchecked_transaction()?;
         
-        let tx = self.conn.transaction()?;
+        let stored_size = self.chunks.put(chunks)?;
+        let hashes: Vec<u8> = chunks.iter().flat_map(|c| c.hash.to_le_bytes()).collect();
+        tx.execute(
+            "INSERT INTO pending_refs (token, hashes, created_at) VALUES (?, ?, ?)",
+            params![token, hashes, chrono_timestamp()]
+        )?;
         
-        {
-            let mut stmt_get = tx.prepare_cached(
-                "SELECT ref_count FROM chunks WHERE hash = ?"
-            )?;
-            let mut stmt_insert = tx.prepare_cached(
-                "INSERT INTO chunks (hash, data, original_size, ref_count) VALUES (?, ?, ?, 1)"
-            )?;
-            let mut stmt_update = tx.prepare_cached(
-                "UPDATE chunks SET ref_count = ref_count + 1 WHERE hash = ?"
+        tx.commit()?;
+        Ok(stored_size)
+    }
+    
+    /// 撤销未提交的归档登记的块引用（`token` 为 `None` 时撤销全部），返回撤销的引用数
+    ///
+    /// SQLite 后端在同一事务中删除登记并释放引用；不随事务回滚的后端先删除登记再释放引用，
+    /// 中途崩溃只会留下多余的引用计数，由 `fsck` 修复。
+    pub(crate) fn rollback_pending(&mut self, token: Option<&str>) -> Result<usize, StoreError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let blobs: Vec<Vec<u8>> = {
+            let mut stmt = tx.prepare(
+                "SELECT hashes FROM pending_refs WHERE ?1 IS NULL OR token = ?1"
             )?;
-            
-            for chunk in chunks {
-                let hash_bytes = chunk.hash.to_le_bytes();
-                
-                let exists: Result<i32, _> = stmt_get.query_row([&hash_bytes[..]], |row| row.get(0));
-                
-                if exists.is_ok() {
-                    stmt_update.execute([&hash_bytes[..]])?;
-                } else {
-                    stmt_insert.execute(params![
-                        &hash_bytes[..],
-                        &chunk.compressed,
-                        chunk.original_size as i64
-                    ])?;
-                    stored_size += chunk.compressed.len() as u64;
-                }
-            }
-        }
+            let rows = stmt.query_map([token], |row| row.get(0))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let hashes: Vec<u128> = blobs.iter()
+            .flat_map(|blob| blob.chunks_exact(16))
+            .map(|bytes| u128::from_le_bytes(bytes.try_into().expect("16-byte hash")))
+            .collect();
         
+        let transactional = self.chunks.transactional();
+        if transactional {
+            self.chunks.unref(&hashes)?;
+        }
+        tx.execute("DELETE FROM pending_refs WHERE ?1 IS NULL OR token = ?1", [token])?;
         tx.commit()?;
-        Ok(stored_size)
+        if !transactional {
+            self.chunks.unref(&hashes)?;
+        }
+        Ok(hashes.len())
     }
     
-    /// 读取块
-    pub fn read_chunks(&self, hashes: &[u128]) -> Result<Vec<Vec<u8>>, StoreError> {
-        let mut stmt = self.conn.prepare_cached(
-            "SELECT data, original_size FROM chunks WHERE hash = ?"
-        )?;
+    /// 在同一事务中释放旧引用、确认归档登记的块引用、保存清单并记录文件操作日志
+    pub(crate) fn commit_mod(&mut self, commit: &ModCommit, manifest: &str) -> Result<(), StoreError> {
+        let tx = self.conn.unchecked_transaction()?;
         
-        let mut results = Vec::with_capacity(hashes.len());
+        // 先增加沿用的引用再释放旧引用；不随事务回滚的后端在提交成功后才释放，
+        // 提交失败时只会留下多余的引用计数
+        self.chunks.add_ref(commit.reused)?;
+        let transactional = self.chunks.transactional();
+        if transactional {
+            self.chunks.unref(commit.released)?;
+        }
+        tx.execute("DELETE FROM pending_refs WHERE token = ?", [commit.journal.0])?;
         
-        for hash in hashes {
-            let hash_bytes = hash.to_le_bytes();
-            let (compressed, original_size): (Vec<u8>, i64) = stmt
-                .query_row([&hash_bytes[..]], |row| Ok((row.get(0)?, row.get(1)?)))
-                .map_err(|_| StoreError::ChunkNotFound(format!("{:032x}", hash)))?;
-            
-            let decompressed = zstd::bulk::decompress(&compressed, original_size as usize)
-                .map_err(|e| StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
-            
-            results.push(decompressed);
+        if let Some(version) = commit.keep_version {
+            tx.execute(
+                "INSERT INTO mod_versions (id, version, name, manifest, created_at)
+                 SELECT id, ?, name, manifest, created_at FROM mods WHERE id = ?",
+                params![version, commit.id]
+            )?;
         }
         
-        Ok(results)
-    }
-    
-    /// 减少块引用计数
-    pub fn decrement_chunk_refs(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
-        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO mods (id, name, manifest, created_at) VALUES (?, ?, ?, ?)",
+            params![commit.id, commit.name, manifest, commit.created_at]
+        )?;
         
+        tx.execute("DELETE FROM file_cache WHERE mod_id = ?", [commit.id])?;
         {
             let mut stmt = tx.prepare_cached(
-                "UPDATE chunks SET ref_count = ref_count - 1 WHERE hash = ?"
+                "INSERT OR REPLACE INTO file_cache (mod_id, path, size, mtime, checksum) VALUES (?, ?, ?, ?, ?)"
             )?;
-            
-            for hash in hashes {
-                let hash_bytes = hash.to_le_bytes();
-                stmt.execute([&hash_bytes[..]])?;
+            for file in commit.cache {
+                stmt.execute(params![commit.id, file.path, file.size as i64, file.mtime, file.checksum])?;
             }
         }
+        insert_pending_op(&tx, commit.journal)?;
         
         tx.commit()?;
+        if !transactional {
+            self.release_committed(commit.released);
+        }
         Ok(())
     }
     
-    /// 垃圾回收
-    pub fn gc(&mut self) -> Result<(usize, u64), StoreError> {
-        let stats: (i64, i64) = self.conn.query_row(
-            "SELECT COUNT(*), COALESCE(SUM(LENGTH(data)), 0) FROM chunks WHERE ref_count <= 0",
-            [],
-            |row| Ok((row.get(0)?, row.get(1)?))
+    /// 在同一事务中删除 mod（含历史版本）、释放块引用并记录文件操作日志
+    pub(crate) fn commit_remove(
+        &mut self,
+        id: &str,
+        released: &[u128],
+        journal: (&str, &str),
+    ) -> Result<(), StoreError> {
+        let tx = self.conn.unchecked_transaction()?;
+        
+        let transactional = self.chunks.transactional();
+        if transactional {
+            self.chunks.unref(released)?;
+        }
+        tx.execute("DELETE FROM mod_versions WHERE id = ?", [id])?;
+        tx.execute("DELETE FROM mods WHERE id = ?", [id])?;
+        tx.execute("DELETE FROM file_cache WHERE mod_id = ?", [id])?;
+        insert_pending_op(&tx, journal)?;
+        
+        tx.commit()?;
+        if !transactional {
+            self.release_committed(released);
+        }
+        Ok(())
+    }
+    
+    /// 清单提交后释放不随事务提交的后端中的旧引用
+    ///
+    /// 提交已经生效，失败时不能再报告为提交失败：只会留下多余的引用计数，由 `fsck` 修复。
+    fn release_committed(&mut self, released: &[u128]) {
+        let _ = self.chunks.unref(released);
+    }
+    
+    /// 未完成的文件操作 (token, plan)
+    pub(crate) fn list_pending_ops(&self) -> Result<Vec<(String, String)>, StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT token, plan FROM pending_ops ORDER BY created_at"
         )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+    
+    /// 读取块（按顺序返回，重复的块只解码一次）
+    pub fn read_chunks(&self, hashes: &[u128]) -> Result<Vec<Vec<u8>>, StoreError> {
+        let chunks = self.read_chunk_map(hashes)?;
+        Ok(hashes.iter().map(|hash| chunks[hash].clone()).collect())
+    }
+    
+    /// 读取一组块，返回 hash → 原始数据
+    ///
+    /// 按批从后端读取，在 rayon 上并行解码；每个不同的块只读取和解码一次。
+    pub fn read_chunk_map(&self, hashes: &[u128]) -> Result<HashMap<u128, Vec<u8>>, StoreError> {
+        let mut unique = hashes.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
         
-        self.conn.execute("DELETE FROM chunks WHERE ref_count <= 0", [])?;
+        let mut chunks = HashMap::with_capacity(unique.len());
+        for batch in unique.chunks(READ_BATCH) {
+            let mut raw = self.chunks.get_many(batch)?;
+            let mut encoded = Vec::with_capacity(batch.len());
+            let mut dictionaries = HashMap::new();
+            for hash in batch {
+                let chunk = raw.remove(hash)
+                    .ok_or_else(|| StoreError::ChunkNotFound(format!("{:032x}", hash)))?;
+                if let Some(id) = chunk.2.dict_id() {
+                    if let std::collections::hash_map::Entry::Vacant(e) = dictionaries.entry(id) {
+                        e.insert(self.decoder_dictionary(id)?);
+                    }
+                }
+                encoded.push((*hash, chunk));
+            }
+            
+            let decoded: Vec<(u128, Vec<u8>)> = encoded
+                .into_par_iter()
+                .map(|(hash, (data, original_size, codec))| {
+                    let dict = codec.dict_id().map(|id| &*dictionaries[&id]);
+                    Ok((hash, decode_chunk(&data, original_size, codec, dict)?))
+                })
+                .collect::<Result<_, StoreError>>()?;
+            chunks.extend(decoded);
+        }
+        Ok(chunks)
+    }
+    
+    /// 读取块的原始（压缩）数据，不解压
+    pub fn read_chunk_raw(&self, hash: u128) -> Result<Option<RawChunk>, StoreError> {
+        self.chunks.get(hash)
+    }
+    
+    /// 按块的编码方式解码数据
+    pub fn decompress(&self, compressed: &[u8], original_size: usize, codec: Codec) -> Result<Vec<u8>, StoreError> {
+        match codec.dict_id() {
+            Some(id) => decode_chunk(compressed, original_size, codec, Some(&*self.decoder_dictionary(id)?)),
+            None => decode_chunk(compressed, original_size, codec, None),
+        }
+    }
+    
+    /// 加载解压字典（按 id 缓存）
+    pub(crate) fn decoder_dictionary(&self, id: i64) -> Result<Arc<DecoderDictionary<'static>>, StoreError> {
+        if let Some(dict) = self.dictionaries.borrow().get(&id) {
+            return Ok(dict.clone());
+        }
+        let data: Vec<u8> = self.conn
+            .query_row("SELECT data FROM dictionaries WHERE id = ?", [id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| StoreError::DictionaryNotFound(id.to_string()))?;
+        let dict = Arc::new(DecoderDictionary::copy(&data));
+        self.dictionaries.borrow_mut().insert(id, dict.clone());
+        Ok(dict)
+    }
+    
+    /// 减少块引用计数
+    pub fn decrement_chunk_refs(&mut self, hashes: &[u128]) -> Result<(), StoreError> {
+        self.chunks.unref(hashes)
+    }
+    
+    /// 垃圾回收：删除无引用的块并回收空间，返回 (删除的块数, 释放的字节数)
+    pub fn gc(&mut self) -> Result<(usize, u64), StoreError> {
+        let result = self.chunks.sweep()?;
         self.conn.execute("VACUUM", [])?;
-        
-        Ok((stats.0 as usize, stats.1 as u64))
+        Ok(result)
+    }
+    
+    /// 块后端名称
+    pub fn backend_name(&self) -> &str {
+        self.chunks.name()
+    }
+    
+    /// 把已有的块转换到同一后端的另一种布局（sqlite 与 pack 互转），返回移动的块数
+    ///
+    /// 每批块在独立事务中移动，中断后再次执行会继续迁移剩余的块。
+    pub fn migrate_backend(&mut self, backend: BackendKind) -> Result<usize, StoreError> {
+        let moved = self.chunks.convert(backend)?;
+        self.conn.execute("VACUUM", [])?;
+        Ok(moved)
     }
     
     /// 保存 mod 清单
@@ -198,6 +451,61 @@ impl ChunkStore {
         Ok(())
     }
     
+    /// mod 当前清单中文件的缓存记录（按路径）
+    pub(crate) fn load_file_cache(&self, id: &str) -> Result<HashMap<String, CachedFile>, StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, size, mtime, checksum FROM file_cache WHERE mod_id = ?"
+        )?;
+        let rows = stmt.query_map([id], |row| {
+            Ok(CachedFile {
+                path: row.get(0)?,
+                size: row.get::<_, i64>(1)? as u64,
+                mtime: row.get(2)?,
+                checksum: row.get(3)?,
+            })
+        })?;
+        let mut cache = HashMap::new();
+        for file in rows {
+            let file = file?;
+            cache.insert(file.path.clone(), file);
+        }
+        Ok(cache)
+    }
+    
+    /// 下一个历史版本号
+    pub fn next_mod_version(&self, id: &str) -> Result<i64, StoreError> {
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM mod_versions WHERE id = ?",
+            [id],
+            |row| row.get(0)
+        )?)
+    }
+    
+    /// 列出 mod 的历史版本 (version, created_at)
+    pub fn list_mod_versions(&self, id: &str) -> Result<Vec<(i64, i64)>, StoreError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT version, created_at FROM mod_versions WHERE id = ? ORDER BY version"
+        )?;
+        
+        let rows = stmt.query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+    
+    /// 获取历史版本的清单
+    pub fn get_mod_version(&self, id: &str, version: i64) -> Result<Option<String>, StoreError> {
+        let result = self.conn.query_row(
+            "SELECT manifest FROM mod_versions WHERE id = ? AND version = ?",
+            params![id, version],
+            |row| row.get(0)
+        );
+        
+        match result {
+            Ok(data) => Ok(Some(data)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    
     /// 获取 mod 清单
     pub fn get_mod(&self, id: &str) -> Result<Option<(String, String)>, StoreError> {
         let result = self.conn.query_row(
@@ -232,32 +540,56 @@ impl ChunkStore {
         rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
     }
     
-    /// 获取统计信息
-    pub fn get_stats(&self) -> Result<StoreStats, StoreError> {
-        let (unique_chunks, total_stored, total_refs): (i64, i64, i64) = self.conn.query_row(
-            "SELECT COUNT(*), COALESCE(SUM(LENGTH(data)), 0), COALESCE(SUM(ref_count), 0) FROM chunks",
-            [],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-        )?;
-        
-        let avg_original: f64 = self.conn.query_row(
-            "SELECT COALESCE(AVG(original_size), 4096.0) FROM chunks",
-            [],
-            |row| row.get(0)
-        )?;
-        
-        let mod_count: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM mods",
-            [],
-            |row| row.get(0)
-        )?;
-        
-        Ok(StoreStats {
-            mod_count: mod_count as usize,
-            unique_chunks: unique_chunks as usize,
-            total_stored_size: total_stored as u64,
-            total_original_size: (total_refs as f64 * avg_original) as u64,
-        })
+    /// 读取存储元数据（schema_version、chunk_size、hash_algorithm 等）
+    pub fn meta(&self, key: &str) -> Result<Option<String>, StoreError> {
+        schema::get_meta(&self.conn, key)
+    }
+    
+    /// 存储创建时使用的块大小
+    pub fn chunk_size(&self) -> Result<usize, StoreError> {
+        let value = self.meta("chunk_size")?.unwrap_or_default();
+        value.parse()
+            .map_err(|_| StoreError::UnsupportedVersion(format!("invalid chunk_size in meta: {}", value)))
+    }
+    
+    /// 存储保存的分块和压缩配置（旧存储只记录了块大小）
+    pub fn chunk_config(&self) -> Result<ChunkConfig, StoreError> {
+        let mut config = ChunkConfig {
+            strategy: ChunkingStrategy::Fixed { size: self.chunk_size()? },
+            ..ChunkConfig::default()
+        };
+        if let Some(value) = self.meta("chunking")? {
+            config.strategy = serde_json::from_str(&value)
+                .map_err(|_| StoreError::UnsupportedVersion(format!("invalid chunking in meta: {}", value)))?;
+        }
+        // meta 可能被手工修改，与 set_chunking 一样校验
+        config.strategy.validate()?;
+        if let Some(value) = self.meta("compression_level")? {
+            config.compression_level = value.parse()
+                .map_err(|_| StoreError::UnsupportedVersion(format!("invalid compression_level in meta: {}", value)))?;
+        }
+        if let Some(value) = self.meta("codec")? {
+            config.codec = Codec::from_name(&value)
+                .ok_or_else(|| StoreError::UnsupportedVersion(format!("invalid codec in meta: {}", value)))?;
+        }
+        if let Some(value) = self.meta("hash_algorithm")? {
+            config.hash_algorithm = HashAlgorithm::from_name(&value)
+                .ok_or_else(|| StoreError::UnsupportedVersion(format!("unknown hash algorithm: {}", value)))?;
+        }
+        Ok(config)
+    }
+    
+    /// 保存之后打开存储时使用的分块和压缩配置（hash 算法只由 `rehash` 修改）
+    pub(crate) fn save_chunk_config(&self, config: &ChunkConfig) -> Result<(), StoreError> {
+        let strategy = serde_json::to_string(&config.strategy)
+            .map_err(|e| StoreError::InvalidConfig(e.to_string()))?;
+        let tx = self.conn.unchecked_transaction()?;
+        schema::set_meta(&tx, "chunk_size", &config.chunk_size().to_string())?;
+        schema::set_meta(&tx, "chunking", &strategy)?;
+        schema::set_meta(&tx, "compression_level", &config.compression_level.to_string())?;
+        schema::set_meta(&tx, "codec", config.codec.name())?;
+        tx.commit()?;
+        Ok(())
     }
     
     pub fn base_path(&self) -> &str {
@@ -265,10 +597,23 @@ impl ChunkStore {
     }
 }
 
-fn chrono_timestamp() -> i64 {
+pub(crate) fn insert_pending_op(tx: &Transaction, (token, plan): (&str, &str)) -> Result<(), StoreError> {
+    tx.execute(
+        "INSERT INTO pending_ops (token, plan, created_at) VALUES (?, ?, ?)",
+        params![token, plan, chrono_timestamp()]
+    )?;
+    Ok(())
+}
+
+pub(crate) fn clear_pending_op(conn: &Connection, token: &str) -> Result<(), StoreError> {
+    conn.execute("DELETE FROM pending_ops WHERE token = ?", [token])?;
+    Ok(())
+}
+
+pub(crate) fn chrono_timestamp() -> i64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
+        .unwrap_or_default()
         .as_millis() as i64
 }
 
@@ -276,7 +621,7 @@ fn chrono_timestamp() -> i64 {
 mod tests {
     use super::*;
     use tempfile::tempdir;
-
+    
     #[test]
     fn test_store_and_read() {
         let dir = tempdir().unwrap();
@@ -287,6 +632,7 @@ mod tests {
                 hash: 12345,
                 compressed: zstd::bulk::compress(b"hello", 3).unwrap(),
                 original_size: 5,
+                codec: Codec::Zstd,
             },
         ];
         
@@ -295,4 +641,12 @@ mod tests {
         let read = store.read_chunks(&[12345]).unwrap();
         assert_eq!(read[0], b"hello");
     }
+    
+    #[test]
+    fn test_reject_invalid_chunk_config() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        schema::set_meta(&store.conn, "chunking", r#"{"type":"fixed","size":0}"#).unwrap();
+        assert!(matches!(store.chunk_config(), Err(StoreError::InvalidConfig(_))));
+    }
 }
@@ -0,0 +1,276 @@
+//! 完整性校验模块
+//!
+//! 逐块解压并重新计算 hash，与存储时的 key 比对；
+//! 再用清单中记录的整文件校验和检查重建结果。
+//! `verify_all` 同时校验历史版本：它们的块引用和保留文件同样需要在提取时可用。
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::archive::{parse_hash, FileManifest, ModArchive, ModManifest};
+use crate::chunk::{decompress_chunk, HashAlgorithm};
+use crate::store::StoreError;
+
+/// 单个 mod 的校验结果
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub mod_id: String,
+    /// 历史版本号（当前清单为 `None`）
+    pub version: Option<i64>,
+    /// 清单无法读取或校验中途出错时的错误信息
+    pub manifest_error: Option<String>,
+    /// 已检查的唯一块数
+    pub chunks_checked: usize,
+    /// 解压失败或 hash 不匹配的块
+    pub corrupted_chunks: Vec<String>,
+    /// 数据库中不存在的块
+    pub missing_chunks: Vec<String>,
+    /// 缺失的 `compressed/*.zst` 文件
+    pub missing_compressed: Vec<String>,
+    /// 缺失的保留文件
+    pub missing_preserved: Vec<String>,
+    /// 整文件校验和不匹配的文件
+    pub checksum_mismatches: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.manifest_error.is_none()
+            && self.corrupted_chunks.is_empty()
+            && self.missing_chunks.is_empty()
+            && self.missing_compressed.is_empty()
+            && self.missing_preserved.is_empty()
+            && self.checksum_mismatches.is_empty()
+    }
+}
+
+/// 块的校验状态
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ChunkStatus {
+    Ok,
+    Corrupted,
+    Missing,
+}
+
+impl ModArchive {
+    /// 校验单个 mod
+    pub fn verify_mod(&self, mod_id: &str) -> Result<VerifyReport, StoreError> {
+        let manifest = self.load_manifest(mod_id)?
+            .ok_or_else(|| StoreError::ModNotFound(mod_id.to_string()))?;
+        self.verify_manifest(&manifest, &self.preserved_dir(mod_id))
+    }
+    
+    /// 校验 mod 的一个历史版本
+    pub fn verify_version(&self, mod_id: &str, version: i64) -> Result<VerifyReport, StoreError> {
+        let label = format!("{}@{}", mod_id, version);
+        let manifest_json = self.store.get_mod_version(mod_id, version)?
+            .ok_or_else(|| StoreError::ModNotFound(label.clone()))?;
+        let manifest = self.read_manifest(&label, &manifest_json)?;
+        let mut report = self.verify_manifest(&manifest, &self.version_dir(mod_id, version))?;
+        report.version = Some(version);
+        Ok(report)
+    }
+    
+    /// 校验所有 mod 及其历史版本
+    ///
+    /// 单个清单出错时记录在其报告的 `manifest_error` 中，继续校验其余清单。
+    pub fn verify_all(&self) -> Result<Vec<VerifyReport>, StoreError> {
+        let failed = |mod_id: &str, version, e: StoreError| VerifyReport {
+            mod_id: mod_id.to_string(),
+            version,
+            manifest_error: Some(e.to_string()),
+            ..Default::default()
+        };
+        let mut reports = Vec::new();
+        for (id, _, _) in self.list_mods()? {
+            reports.push(self.verify_mod(&id).unwrap_or_else(|e| failed(&id, None, e)));
+            for (version, _) in self.store.list_mod_versions(&id)? {
+                reports.push(self.verify_version(&id, version).unwrap_or_else(|e| failed(&id, Some(version), e)));
+            }
+        }
+        Ok(reports)
+    }
+    
+    fn verify_manifest(&self, manifest: &ModManifest, mods_dir: &Path) -> Result<VerifyReport, StoreError> {
+        let mut report = VerifyReport {
+            mod_id: manifest.id.clone(),
+            ..Default::default()
+        };
+        let mut statuses: HashMap<u128, ChunkStatus> = HashMap::new();
+        
+        for file in &manifest.files {
+            if file.is_compressed() {
+                self.verify_compressed(file, manifest.hash_algorithm, &mut report)?;
+            } else {
+                self.verify_chunked(file, manifest.hash_algorithm, &mut statuses, &mut report)?;
+            }
+        }
+        
+        for relative_path in &manifest.preserved_files {
+            if !mods_dir.join(relative_path).is_file() {
+                report.missing_preserved.push(relative_path.clone());
+            }
+        }
+        
+        report.chunks_checked = statuses.len();
+        Ok(report)
+    }
+    
+    /// 校验分块文件：每个块只检查一次，同时流式计算整文件校验和
+    fn verify_chunked(
+        &self,
+        file: &FileManifest,
+        algorithm: HashAlgorithm,
+        statuses: &mut HashMap<u128, ChunkStatus>,
+        report: &mut VerifyReport,
+    ) -> Result<(), StoreError> {
+        let header = file.dds_metadata.as_ref().and_then(|m| m.header_chunk.as_ref());
+        // 旧版 DDS 清单没有保存文件头，无法重建整文件
+        let mut complete = file.file_type.as_deref() != Some("dds") || header.is_some();
+        let mut hasher = algorithm.hasher();
+        
+        for hash_str in header.into_iter().chain(&file.chunks) {
+            let hash = parse_hash(hash_str)?;
+            let known = statuses.get(&hash).copied();
+            
+            // 已确认完好的块只有在需要计算校验和时才重新读取
+            if known == Some(ChunkStatus::Ok) && (!complete || file.checksum.is_none()) {
+                continue;
+            }
+            if known.is_some_and(|s| s != ChunkStatus::Ok) {
+                complete = false;
+                continue;
+            }
+            
+            let status = match self.store.read_chunk_raw(hash)? {
+                None => ChunkStatus::Missing,
+                Some((compressed, original_size, codec)) => {
+                    match self.store.decompress(&compressed, original_size, codec) {
+                        Ok(data) if algorithm.hash(&data) == hash => {
+                            hasher.update(&data);
+                            ChunkStatus::Ok
+                        }
+                        _ => ChunkStatus::Corrupted,
+                    }
+                }
+            };
+            
+            if known.is_none() {
+                match status {
+                    ChunkStatus::Missing => report.missing_chunks.push(hash_str.clone()),
+                    ChunkStatus::Corrupted => report.corrupted_chunks.push(hash_str.clone()),
+                    ChunkStatus::Ok => {}
+                }
+                statuses.insert(hash, status);
+            }
+            if status != ChunkStatus::Ok {
+                complete = false;
+            }
+        }
+        
+        if let (true, Some(checksum)) = (complete, &file.checksum) {
+            if format!("{:032x}", hasher.finish()) != *checksum {
+                report.checksum_mismatches.push(file.path.clone());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// 校验旧版 buf/ib 压缩文件
+    fn verify_compressed(
+        &self,
+        file: &FileManifest,
+        algorithm: HashAlgorithm,
+        report: &mut VerifyReport,
+    ) -> Result<(), StoreError> {
+        let file_id = file.chunks.first()
+            .ok_or_else(|| StoreError::CorruptManifest(format!("no compressed file id: {}", file.path)))?;
+        let compressed = match fs::read(self.compressed_path(file_id)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                report.missing_compressed.push(file_id.clone());
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        
+        let valid = match decompress_chunk(&compressed, file.original_size as usize) {
+            Ok(data) => file.checksum.as_ref()
+                .is_none_or(|checksum| format!("{:032x}", algorithm.hash(&data)) == *checksum),
+            Err(_) => false,
+        };
+        if !valid {
+            report.checksum_mismatches.push(file.path.clone());
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{ArchiveOptions, OnExisting};
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_verify_detects_damage() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(src.path().join("a.dat"), &data).unwrap();
+        fs::write(src.path().join("a.buf"), &data).unwrap();
+        fs::write(src.path().join("mod.ini"), b"[Constants]").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        
+        let report = archive.verify_mod("m").unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.chunks_checked, 3);
+        
+        // 直接改写数据库中的块数据
+        let conn = rusqlite::Connection::open(store.path().join("store.db")).unwrap();
+        let garbage = zstd::bulk::compress(&[1u8; 4096], 3).unwrap();
+        conn.execute("UPDATE chunks SET data = ?", [&garbage]).unwrap();
+        
+        fs::remove_file(store.path().join("mods").join("m").join("mod.ini")).unwrap();
+        
+        let report = archive.verify_mod("m").unwrap();
+        assert_eq!(report.corrupted_chunks.len(), 3);
+        assert_eq!(report.missing_preserved, vec!["mod.ini".to_string()]);
+    }
+    
+    #[test]
+    fn test_verify_all_continues_and_checks_versions() {
+        let src = tempdir().unwrap();
+        let store = tempdir().unwrap();
+        fs::write(src.path().join("a.dat"), b"first").unwrap();
+        fs::write(src.path().join("mod.ini"), b"v1").unwrap();
+        
+        let mut archive = ModArchive::open(store.path()).unwrap();
+        archive.archive_mod(src.path(), Some("bad"), None).unwrap();
+        archive.archive_mod(src.path(), Some("m"), None).unwrap();
+        fs::write(src.path().join("a.dat"), b"second").unwrap();
+        let version = ArchiveOptions {
+            id: Some("m".to_string()),
+            on_existing: OnExisting::Version,
+            ..Default::default()
+        };
+        archive.archive_mod_with(src.path(), &version).unwrap();
+        
+        let conn = rusqlite::Connection::open(store.path().join("store.db")).unwrap();
+        conn.execute("UPDATE mods SET manifest = '{' WHERE id = 'bad'", []).unwrap();
+        fs::remove_file(store.path().join("versions/m/1/mod.ini")).unwrap();
+        
+        let mut reports = archive.verify_all().unwrap();
+        reports.sort_by_key(|r| (r.mod_id.clone(), r.version));
+        let summary: Vec<_> = reports.iter().map(|r| (r.mod_id.as_str(), r.version, r.is_ok())).collect();
+        assert_eq!(summary, [("bad", None, false), ("m", None, true), ("m", Some(1), false)]);
+        assert!(reports[0].manifest_error.is_some());
+        assert_eq!(reports[2].missing_preserved, vec!["mod.ini".to_string()]);
+    }
+}